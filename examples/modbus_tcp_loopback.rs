@@ -0,0 +1,166 @@
+//! Runs `ModbusTcpServer` on an embassy-net stack wired to a second, client stack
+//! through in-memory queues, so the server is exercised without an Ethernet PHY.
+#![no_std]
+#![no_main]
+
+use core::task::Context;
+
+use defmt::{error, info};
+use embassy_executor::Spawner;
+use embassy_net::driver::{Capabilities, Driver, HardwareAddress, LinkState, RxToken, TxToken};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Runner, Stack, StackResources, StaticConfigV4};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_time::Timer;
+use heapless::Vec;
+use niva_embassy::components::server::modbus_tcp_server::{ModbusTcpServer, TcpBuffers, MODBUS_TCP_PORT};
+use rmodbus::server::storage::ModbusStorage;
+use { defmt_rtt as _, panic_probe as _};
+
+const MTU: usize = 1514;
+const SERVER_IP: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+const CLIENT_IP: Ipv4Address = Ipv4Address::new(10, 0, 0, 2);
+
+type Packet = Vec<u8, MTU>;
+type Queue = Channel<CriticalSectionRawMutex, Packet, 4>;
+
+static SERVER_TO_CLIENT: Queue = Channel::new();
+static CLIENT_TO_SERVER: Queue = Channel::new();
+static STORAGE: Mutex<CriticalSectionRawMutex, ModbusStorage<16, 16, 16, 16>> = Mutex::new(ModbusStorage::new());
+
+/// One end of the in-memory Ethernet link
+struct Link {
+    rx: &'static Queue,
+    tx: &'static Queue,
+    mac: [u8; 6],
+}
+
+struct LinkRx(Packet);
+
+struct LinkTx(&'static Queue);
+
+impl RxToken for LinkRx {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(mut self, f: F) -> R {
+        f(&mut self.0)
+    }
+}
+
+impl TxToken for LinkTx {
+    fn consume<R, F: FnOnce(&mut [u8]) -> R>(self, len: usize, f: F) -> R {
+        let mut packet = Packet::new();
+        packet.resize_default(len).unwrap();
+        let result = f(&mut packet);
+        // a full queue drops the frame like a congested wire, TCP retransmits it
+        let _ = self.0.try_send(packet);
+        result
+    }
+}
+
+impl Driver for Link {
+    type RxToken<'a> = LinkRx where Self: 'a;
+    type TxToken<'a> = LinkTx where Self: 'a;
+
+    fn receive(&mut self, cx: &mut Context) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        match self.rx.poll_receive(cx) {
+            core::task::Poll::Ready(packet) => Some((LinkRx(packet), LinkTx(self.tx))),
+            core::task::Poll::Pending => None,
+        }
+    }
+
+    fn transmit(&mut self, cx: &mut Context) -> Option<Self::TxToken<'_>> {
+        match self.tx.poll_ready_to_send(cx) {
+            core::task::Poll::Ready(()) => Some(LinkTx(self.tx)),
+            core::task::Poll::Pending => None,
+        }
+    }
+
+    fn link_state(&mut self, _cx: &mut Context) -> LinkState {
+        LinkState::Up
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        capabilities.max_transmission_unit = MTU;
+        capabilities
+    }
+
+    fn hardware_address(&self) -> HardwareAddress {
+        HardwareAddress::Ethernet(self.mac)
+    }
+}
+
+fn static_config(address: Ipv4Address) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        gateway: None,
+        dns_servers: Vec::new(),
+    })
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn net_task(mut runner: Runner<'static, Link>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn server_task(stack: Stack<'static>) -> ! {
+    let buffers = cortex_m::singleton!(: [TcpBuffers; 2] = [TcpBuffers::new(), TcpBuffers::new()]).unwrap();
+    ModbusTcpServer::new(&STORAGE, 1).run(stack, buffers).await
+}
+
+/// Send one MBAP framed request and return the length of the response
+async fn transaction(socket: &mut TcpSocket<'_>, request: &[u8], response: &mut [u8]) -> Option<usize> {
+    let mut written = 0;
+    while written < request.len() {
+        written += socket.write(&request[written..]).await.ok()?;
+    }
+    let count = socket.read(response).await.ok()?;
+    (count > 0).then_some(count)
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let _p = embassy_stm32::init(Default::default());
+
+    let server_resources = cortex_m::singleton!(: StackResources<4> = StackResources::new()).unwrap();
+    let client_resources = cortex_m::singleton!(: StackResources<2> = StackResources::new()).unwrap();
+    let server_link = Link { rx: &CLIENT_TO_SERVER, tx: &SERVER_TO_CLIENT, mac: [0x02, 0, 0, 0, 0, 1] };
+    let client_link = Link { rx: &SERVER_TO_CLIENT, tx: &CLIENT_TO_SERVER, mac: [0x02, 0, 0, 0, 0, 2] };
+    let (server_stack, server_runner) = embassy_net::new(server_link, static_config(SERVER_IP), server_resources, 1);
+    let (client_stack, client_runner) = embassy_net::new(client_link, static_config(CLIENT_IP), client_resources, 2);
+
+    spawner.must_spawn(net_task(server_runner));
+    spawner.must_spawn(net_task(client_runner));
+    spawner.must_spawn(server_task(server_stack));
+    client_stack.wait_config_up().await;
+
+    let mut rx = [0u8; 512];
+    let mut tx = [0u8; 512];
+    let mut socket = TcpSocket::new(client_stack, &mut rx, &mut tx);
+    if let Err(e) = socket.connect((SERVER_IP, MODBUS_TCP_PORT)).await {
+        error!("connect {}", e);
+        return;
+    }
+
+    let mut response = [0u8; 260];
+    // FC06: holding register 3 = 1234
+    let write = [0, 1, 0, 0, 0, 6, 1, 0x06, 0, 3, 0x04, 0xD2];
+    match transaction(&mut socket, &write, &mut response).await {
+        Some(count) => info!("write response {}", &response[..count]),
+        None => error!("write failed"),
+    }
+    // FC03: read holding register 3
+    let read = [0, 2, 0, 0, 0, 6, 1, 0x03, 0, 3, 0, 1];
+    match transaction(&mut socket, &read, &mut response).await {
+        Some(count) if response[..count] == [0, 2, 0, 0, 0, 5, 1, 0x03, 2, 0x04, 0xD2] => info!("loopback ok"),
+        Some(count) => error!("unexpected read response {}", &response[..count]),
+        None => error!("read failed"),
+    }
+    socket.close();
+
+    loop {
+        Timer::after_secs(1).await;
+    }
+}
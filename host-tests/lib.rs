@@ -0,0 +1,15 @@
+//! Std build of the firmware modules that only depend on `core`. They are included with
+//! `#[path]`, so they compile unchanged for the host. The firmware target is set in
+//! `.cargo/config.toml`, pass the host target explicitly:
+//!
+//! `cargo test --manifest-path host-tests/Cargo.toml --target x86_64-unknown-linux-gnu`
+
+// siblings at the crate root, so `super::od` in `eds` and `super::def` in `export` resolve as in the firmware
+#[path = "../src/components/server/modbus_can_server/od.rs"]
+pub mod od;
+#[path = "../src/components/server/modbus_can_server/eds.rs"]
+pub mod eds;
+#[path = "../src/components/server/register_map/def.rs"]
+pub mod def;
+#[path = "../src/components/server/register_map/export.rs"]
+pub mod export;
@@ -0,0 +1,111 @@
+use niva_embassy_host_tests::eds::{write_eds, EdsInfo, Error};
+use niva_embassy_host_tests::od::{AccessType, DataType, ObjectDictionary, OdEntry, PdoMappedObject, PdoMapping};
+
+const INFO: EdsInfo = EdsInfo {
+    file_name: "test.eds",
+    file_version: 1,
+    description: "Host test",
+    created_by: "host-tests",
+    vendor_name: "Niva",
+    vendor_number: 0x1234,
+    product_name: "Test device",
+    product_number: 0x42,
+    revision_number: 1,
+    order_code: "T-1",
+    device_type: 0,
+    baud_rates: &[125, 250],
+};
+
+static ENTRIES: [OdEntry; 4] = [
+    OdEntry::new(0, 0, "Relay", DataType::Boolean, AccessType::Rw),
+    OdEntry::new(5, 2, "Setpoint", DataType::Unsigned16, AccessType::Rw).with_default(100).mappable(),
+    OdEntry::new(5, 3, "Measured", DataType::Unsigned16, AccessType::Ro).mappable(),
+    OdEntry::new(7, 0, "Enable", DataType::Boolean, AccessType::Rw),
+];
+
+static TPDO_OBJECTS: [PdoMappedObject; 1] = [PdoMappedObject { index: 5, sub_index: 3, bits: 16 }];
+static TPDOS: [PdoMapping; 1] = [PdoMapping { number: 0, objects: &TPDO_OBJECTS }];
+
+fn generate(od: &ObjectDictionary) -> Result<String, Error> {
+    let mut eds = String::new();
+    write_eds(&mut eds, &INFO, od)?;
+    Ok(eds)
+}
+
+/// Lines of the section `name` up to the next empty line
+fn section<'a>(eds: &'a str, name: &str) -> Vec<&'a str> {
+    let header = format!("[{}]", name);
+    let mut lines = eds.lines().skip_while(|l| *l != header);
+    assert!(lines.next().is_some(), "section {} missing", header);
+    lines.take_while(|l| !l.is_empty()).collect()
+}
+
+#[test]
+fn registers_are_mapped_into_the_manufacturer_range() {
+    let od = ObjectDictionary { entries: &ENTRIES, tpdos: &TPDOS, rpdos: &[] };
+    let eds = generate(&od).unwrap();
+
+    assert_eq!(section(&eds, "ManufacturerObjects"), ["SupportedObjects=3", "1=0x2000", "2=0x2005", "3=0x2007"]);
+    assert!(!eds.contains("[0000]"));
+    assert!(!eds.contains("[0005"));
+}
+
+#[test]
+fn single_coil_is_a_var() {
+    let od = ObjectDictionary { entries: &ENTRIES, tpdos: &[], rpdos: &[] };
+    let eds = generate(&od).unwrap();
+
+    let var = section(&eds, "2000");
+    assert!(var.contains(&"ObjectType=0x7"));
+    assert!(var.contains(&"DataType=0x0001"));
+    assert!(!eds.contains("[2000sub"));
+}
+
+#[test]
+fn record_has_highest_sub_index() {
+    let od = ObjectDictionary { entries: &ENTRIES, tpdos: &[], rpdos: &[] };
+    let eds = generate(&od).unwrap();
+
+    let record = section(&eds, "2005");
+    assert!(record.contains(&"ObjectType=0x9"));
+    assert!(record.contains(&"SubNumber=0x3"));
+    let sub0 = section(&eds, "2005sub0");
+    assert!(sub0.contains(&"ParameterName=Highest sub-index supported"));
+    assert!(sub0.contains(&"DefaultValue=0x3"));
+    let setpoint = section(&eds, "2005sub2");
+    assert!(setpoint.contains(&"ParameterName=Setpoint"));
+    assert!(setpoint.contains(&"AccessType=rw"));
+    assert!(setpoint.contains(&"DefaultValue=0x64"));
+    assert!(setpoint.contains(&"PDOMapping=1"));
+    assert!(section(&eds, "2005sub3").contains(&"AccessType=ro"));
+}
+
+#[test]
+fn pdo_mapping_uses_object_indexes() {
+    let od = ObjectDictionary { entries: &ENTRIES, tpdos: &TPDOS, rpdos: &[] };
+    let eds = generate(&od).unwrap();
+
+    assert!(section(&eds, "1A00sub1").contains(&"DefaultValue=0x20050310"));
+    assert!(section(&eds, "1800sub1").contains(&"DefaultValue=$NODEID+0x180"));
+}
+
+#[test]
+fn register_beyond_the_manufacturer_range_is_rejected() {
+    static ENTRIES: [OdEntry; 1] = [OdEntry::new(0x4000, 2, "Too far", DataType::Unsigned16, AccessType::Ro)];
+    let od = ObjectDictionary { entries: &ENTRIES, tpdos: &[], rpdos: &[] };
+    assert!(matches!(generate(&od), Err(Error::IndexOutOfRange(0x4000))));
+}
+
+#[test]
+fn coil_and_registers_share_an_object() {
+    static ENTRIES: [OdEntry; 2] = [
+        OdEntry::new(3, 0, "Run", DataType::Boolean, AccessType::Rw),
+        OdEntry::new(3, 2, "Speed", DataType::Unsigned16, AccessType::Rw),
+    ];
+    let od = ObjectDictionary { entries: &ENTRIES, tpdos: &[], rpdos: &[] };
+    let eds = generate(&od).unwrap();
+
+    assert!(section(&eds, "2003").contains(&"SubNumber=0x2"));
+    assert!(section(&eds, "2003sub0").contains(&"ParameterName=Run"));
+    assert!(section(&eds, "2003sub2").contains(&"ParameterName=Speed"));
+}
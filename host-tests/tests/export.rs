@@ -0,0 +1,71 @@
+use niva_embassy_host_tests::def::{Area, RawType, Register, RegisterType, WordOrder};
+use niva_embassy_host_tests::export::{write_csv, write_json};
+
+static REGISTERS: [Register; 3] = [
+    Register::new("Setpoint", Area::Holding, 10, RegisterType::Scaled { raw: RawType::I16, scale: 0.1 }).unit("°C"),
+    Register::new("Counter", Area::Input, 20, RegisterType::U32).word_order(WordOrder::LowFirst),
+    Register::new("Relay", Area::Coil, 0, RegisterType::Bool),
+];
+
+fn csv(registers: &[Register]) -> String {
+    let mut out = String::new();
+    write_csv(&mut out, registers).unwrap();
+    out
+}
+
+fn json(registers: &[Register]) -> String {
+    let mut out = String::new();
+    write_json(&mut out, registers).unwrap();
+    out
+}
+
+#[test]
+fn csv_has_one_row_per_register() {
+    assert_eq!(
+        csv(&REGISTERS),
+        "name,area,address,words,type,scale,word_order,unit,access\n\
+         Setpoint,holding,10,1,i16,0.1,high_first,°C,rw\n\
+         Counter,input,20,2,u32,1,low_first,,ro\n\
+         Relay,coil,0,1,bool,1,high_first,,rw\n"
+    );
+}
+
+#[test]
+fn csv_quotes_fields_with_separators_and_doubles_quotes() {
+    let registers = [
+        Register::new("Flow, \"raw\"", Area::Input, 1, RegisterType::U16).unit("l/min"),
+        Register::new("Note", Area::Input, 2, RegisterType::U16).unit("line\nbreak"),
+    ];
+    let out = csv(&registers);
+    let rows: Vec<&str> = out.splitn(2, '\n').collect();
+    assert_eq!(
+        rows[1],
+        "\"Flow, \"\"raw\"\"\",input,1,1,u16,1,high_first,l/min,ro\n\
+         Note,input,2,1,u16,1,high_first,\"line\nbreak\",ro\n"
+    );
+}
+
+#[test]
+fn json_is_an_array_of_objects() {
+    assert_eq!(
+        json(&REGISTERS[1..]),
+        "[\n  \
+         {\"name\": \"Counter\", \"area\": \"input\", \"address\": 20, \"words\": 2, \"type\": \"u32\", \"scale\": 1, \"word_order\": \"low_first\", \"unit\": \"\", \"access\": \"ro\"},\n  \
+         {\"name\": \"Relay\", \"area\": \"coil\", \"address\": 0, \"words\": 1, \"type\": \"bool\", \"scale\": 1, \"word_order\": \"high_first\", \"unit\": \"\", \"access\": \"rw\"}\n\
+         ]\n"
+    );
+}
+
+#[test]
+fn json_escapes_quotes_backslashes_and_control_characters() {
+    let registers = [Register::new("A \"b\" \\c", Area::Holding, 0, RegisterType::U16).unit("x\ty\u{1}")];
+    let out = json(&registers);
+    assert!(out.contains("\"name\": \"A \\\"b\\\" \\\\c\""), "{}", out);
+    assert!(out.contains("\"unit\": \"x\\ty\\u0001\""), "{}", out);
+}
+
+#[test]
+fn empty_table() {
+    assert_eq!(csv(&[]), "name,area,address,words,type,scale,word_order,unit,access\n");
+    assert_eq!(json(&[]), "[\n]\n");
+}
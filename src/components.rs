@@ -1,4 +1,5 @@
 pub mod io;
 pub mod com;
 pub mod mem;
-pub mod server;
\ No newline at end of file
+pub mod server;
+pub mod crc;
@@ -1,2 +1,4 @@
 pub mod rs485;
+pub mod transport;
+pub mod serial_config;
 pub mod indicator_led;
@@ -1,26 +1,45 @@
-use embassy_stm32::{gpio::Output, mode::Async, usart::{Error, Uart}};
-use embassy_time::Timer;
+use embassy_stm32::{gpio::Output, mode::Async, usart::{Config, ConfigError, Error, Uart}};
+use embassy_time::{Duration, Timer};
 
 pub struct Rs485<'a> {
     uart: Uart<'a, Async>,
-    de: Option<Output<'a>>
+    de: Option<Output<'a>>,
+    turnaround: Duration,
 }
 
 impl<'a> Rs485<'a> {
     pub fn new(uart: Uart<'a, Async>, de: Option<Output<'a>>) -> Self {
+        Self::new_with_turnaround(uart, de, Duration::from_millis(2))
+    }
+
+    /// `turnaround` is how long DE is held after the last byte left the transmitter
+    pub fn new_with_turnaround(uart: Uart<'a, Async>, de: Option<Output<'a>>, turnaround: Duration) -> Self {
         Self {
             uart,
-            de
+            de,
+            turnaround,
         }
     }
 
+    pub fn turnaround(&self) -> Duration {
+        self.turnaround
+    }
+    pub fn set_turnaround(&mut self, turnaround: Duration) {
+        self.turnaround = turnaround
+    }
+
+    /// Change baud rate, parity or stop bits, call it once the line is idle
+    pub fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        self.uart.set_config(config)
+    }
+
     pub async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
         if let Some(de) = self.de.as_mut() {
             de.set_high();
         }
         self.uart.write(buffer).await?;
         if let Some(de) = self.de.as_mut() {
-            Timer::after_millis(2).await;
+            Timer::after(self.turnaround).await;
             de.set_low();
         }
         Ok(())
@@ -0,0 +1,63 @@
+use embassy_stm32::usart::{Config, Parity, StopBits};
+
+/// Holding registers used by `SerialSettings`: baud rate high and low word, parity, stop bits
+pub const SERIAL_REGISTERS: usize = 4;
+
+const BAUDRATES: [u32; 11] = [1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600];
+
+/// Serial line settings that can be changed at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerialSettings {
+    pub baudrate: u32,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl SerialSettings {
+    pub const fn new(baudrate: u32, parity: Parity, stop_bits: StopBits) -> Self {
+        Self { baudrate, parity, stop_bits }
+    }
+
+    /// Parity register: 0 none, 1 even, 2 odd. Stop bits register: 1 or 2.
+    /// `None` for unsupported values.
+    pub fn from_registers(registers: [u16; SERIAL_REGISTERS]) -> Option<Self> {
+        let baudrate = (registers[0] as u32) << 16 | registers[1] as u32;
+        if !BAUDRATES.contains(&baudrate) {
+            return None;
+        }
+        let parity = match registers[2] {
+            0 => Parity::ParityNone,
+            1 => Parity::ParityEven,
+            2 => Parity::ParityOdd,
+            _ => return None,
+        };
+        let stop_bits = match registers[3] {
+            1 => StopBits::STOP1,
+            2 => StopBits::STOP2,
+            _ => return None,
+        };
+        Some(Self { baudrate, parity, stop_bits })
+    }
+
+    pub fn to_registers(&self) -> [u16; SERIAL_REGISTERS] {
+        let parity = match self.parity {
+            Parity::ParityEven => 1,
+            Parity::ParityOdd => 2,
+            _ => 0,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::STOP2 => 2,
+            _ => 1,
+        };
+        [(self.baudrate >> 16) as u16, self.baudrate as u16, parity, stop_bits]
+    }
+
+    /// `base` with these settings applied
+    pub fn config(&self, base: Config) -> Config {
+        let mut config = base;
+        config.baudrate = self.baudrate;
+        config.parity = self.parity;
+        config.stop_bits = self.stop_bits;
+        config
+    }
+}
@@ -0,0 +1,65 @@
+use embassy_stm32::{mode::Async, usart::{Config, ConfigError, Error, Uart}};
+
+use super::rs485::Rs485;
+
+/// Serial link used by the Modbus components, either a bare `Uart` or a half-duplex `Rs485`
+#[allow(async_fn_in_trait)]
+pub trait Transport {
+    async fn write(&mut self, buffer: &[u8]) -> Result<(), Error>;
+
+    /// Perform an asynchronous read into `buffer`
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error>;
+
+    /// Perform an asynchronous read with idle line detection enabled
+    async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error>;
+
+    /// Wait until the last written byte has left the shift register (transmission complete)
+    async fn flush(&mut self) -> Result<(), Error>;
+
+    /// Re-apply the serial settings
+    fn set_config(&mut self, config: &Config) -> Result<(), ConfigError>;
+}
+
+impl Transport for Uart<'_, Async> {
+    async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        Uart::write(self, buffer).await
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        Uart::read(self, buffer).await
+    }
+
+    async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        Uart::read_until_idle(self, buffer).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Uart::flush(self).await
+    }
+
+    fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        Uart::set_config(self, config)
+    }
+}
+
+impl Transport for Rs485<'_> {
+    async fn write(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        Rs485::write(self, buffer).await
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        Rs485::read(self, buffer).await
+    }
+
+    async fn read_until_idle(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        Rs485::read_until_idle(self, buffer).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Error> {
+        Rs485::flush(self).await
+    }
+
+    fn set_config(&mut self, config: &Config) -> Result<(), ConfigError> {
+        Rs485::set_config(self, config)
+    }
+}
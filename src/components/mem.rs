@@ -5,6 +5,13 @@ use embassy_stm32::flash;
 pub enum Error {
     Flash(flash::Error),
     NoData,
+    /// Stored chunk does not match its checksum
+    Checksum,
+    /// Register missing in the storage
+    Register(u16),
+    /// Chunk can not hold the registers and their checksum
+    ChunkTooSmall,
 }
 
-pub mod chunked_sector;
\ No newline at end of file
+pub mod chunked_sector;
+pub mod persistence;
\ No newline at end of file
@@ -62,25 +62,27 @@ impl<const SECTOR_OFFSET: usize, const SECTOR_SIZE: usize, const CHUNK_SIZE: usi
             .blocking_read(SECTOR_OFFSET as u32, &mut read_data)
             .map_err(|e| Error::Flash(e))?;
         let last_free_cell_pos =
-            Self::find_first_empty_chunk_pos(&read_data).unwrap_or_else(|| {
-                self.blocking_erase();
-                SECTOR_OFFSET
-            });
+            match Self::find_first_empty_chunk_pos(&read_data) {
+                Some(pos) => pos,
+                None => {
+                    self.blocking_erase()?;
+                    SECTOR_OFFSET
+                },
+            };
         trace!("Last free pos: {}", last_free_cell_pos);
         self.flash
             .blocking_write((last_free_cell_pos) as u32, &chunk.data)
-            .unwrap();
-        Ok(())
+            .map_err(|e| Error::Flash(e))
     }
 
     // erase sector
-    pub fn blocking_erase(&mut self) {
+    pub fn blocking_erase(&mut self) -> Result<(), Error> {
         self.flash
         .blocking_erase(
             SECTOR_OFFSET as u32,
             (SECTOR_OFFSET + SECTOR_SIZE - 1) as u32,
         )
-        .unwrap();
+        .map_err(|e| Error::Flash(e))
     }
 }
 
@@ -146,24 +148,23 @@ impl<const SECTOR_OFFSET: usize, const SECTOR_SIZE: usize, const CHUNK_SIZE: usi
         if let Some(chunk_pos) = self.find_first_empty_chunk_pos()? {
             first_free_chunk_pos = chunk_pos;
         } else {
-            self.blocking_erase();
+            self.blocking_erase()?;
             first_free_chunk_pos = SECTOR_OFFSET;
         }
         trace!("First free pos: {}", first_free_chunk_pos);
         self.flash
             .blocking_write((first_free_chunk_pos) as u32, &chunk.data)
-            .unwrap();
-        Ok(())
+            .map_err(|e| Error::Flash(e))
     }
 
     // erase sector
-    pub fn blocking_erase(&mut self) {
+    pub fn blocking_erase(&mut self) -> Result<(), Error> {
         self.flash
         .blocking_erase(
             SECTOR_OFFSET as u32,
             (SECTOR_OFFSET + SECTOR_SIZE - 1) as u32,
         )
-        .unwrap();
+        .map_err(|e| Error::Flash(e))
     }
 
     // write to first empty chunk
@@ -172,24 +173,23 @@ impl<const SECTOR_OFFSET: usize, const SECTOR_SIZE: usize, const CHUNK_SIZE: usi
         if let Some(chunk_pos) = self.find_first_empty_chunk_pos()? {
             first_free_chunk_pos = chunk_pos;
         } else {
-            self.erase().await;
+            self.erase().await?;
             first_free_chunk_pos = SECTOR_OFFSET;
         }
         trace!("First free pos: {}", first_free_chunk_pos);
         self.flash
             .write((first_free_chunk_pos) as u32, &chunk.data).await
-            .unwrap();
-        Ok(())
+            .map_err(|e| Error::Flash(e))
     }
 
     // erase sector
-    pub async fn erase(&mut self) {
+    pub async fn erase(&mut self) -> Result<(), Error> {
         self.flash
         .erase(
             SECTOR_OFFSET as u32,
             (SECTOR_OFFSET + SECTOR_SIZE - 1) as u32,
         ).await
-        .unwrap();
+        .map_err(|e| Error::Flash(e))
     }
 }
 
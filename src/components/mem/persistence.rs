@@ -0,0 +1,181 @@
+use defmt::{info, warn};
+#[allow(unused_imports)]
+use embassy_futures::yield_now;
+#[allow(unused_imports)]
+use embassy_stm32::flash::Async;
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+
+use super::chunked_sector::{Chunk, ChunkedSector};
+use super::Error;
+use crate::components::crc::crc16;
+use crate::components::server::register_provider::RegisterProvider;
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+
+/// Chunk based flash storage used by `HoldingPersistence`
+#[allow(async_fn_in_trait)]
+pub trait ChunkStorage<const CHUNK_SIZE: usize> {
+    fn blocking_read(&mut self, chunk: &mut Chunk<CHUNK_SIZE>) -> Result<(), Error>;
+
+    /// Store `chunk`, the sector is erased first when it is full
+    async fn write(&mut self, chunk: &Chunk<CHUNK_SIZE>) -> Result<(), Error>;
+}
+
+#[cfg(not(feature = "stm32f405rg"))]
+impl<const SECTOR_OFFSET: usize, const SECTOR_SIZE: usize, const CHUNK_SIZE: usize, MODE> ChunkStorage<CHUNK_SIZE>
+    for ChunkedSector<SECTOR_OFFSET, SECTOR_SIZE, CHUNK_SIZE, MODE>
+{
+    fn blocking_read(&mut self, chunk: &mut Chunk<CHUNK_SIZE>) -> Result<(), Error> {
+        ChunkedSector::blocking_read(self, chunk)
+    }
+
+    /// No async flash driver here: the write, and an erase of a full sector, stall the executor
+    /// until they are done. Other tasks get a turn right before.
+    async fn write(&mut self, chunk: &Chunk<CHUNK_SIZE>) -> Result<(), Error> {
+        yield_now().await;
+        ChunkedSector::blocking_write(self, chunk)
+    }
+}
+
+#[cfg(feature = "stm32f405rg")]
+impl<const SECTOR_OFFSET: usize, const SECTOR_SIZE: usize, const CHUNK_SIZE: usize> ChunkStorage<CHUNK_SIZE>
+    for ChunkedSector<SECTOR_OFFSET, SECTOR_SIZE, CHUNK_SIZE, Async>
+{
+    fn blocking_read(&mut self, chunk: &mut Chunk<CHUNK_SIZE>) -> Result<(), Error> {
+        ChunkedSector::blocking_read(self, chunk)
+    }
+
+    async fn write(&mut self, chunk: &Chunk<CHUNK_SIZE>) -> Result<(), Error> {
+        ChunkedSector::write(self, chunk).await
+    }
+}
+
+/// Keeps a range of holding registers in flash. Registers are stored big endian followed by a CRC16,
+/// so `CHUNK_SIZE` has to hold at least `2 * count + 2` bytes.
+pub struct HoldingPersistence<'a, M: RawMutex, P: RegisterProvider, S: ChunkStorage<CHUNK_SIZE>, const CHUNK_SIZE: usize> {
+    storage: &'a Mutex<M, P>,
+    sector: S,
+    start: u16,
+    count: u16,
+    poll_period: Duration,
+    settle_delay: Duration,
+    error_signal: Option<&'a Signal<M, Error>>,
+}
+
+impl<'a, M: RawMutex, P: RegisterProvider, S: ChunkStorage<CHUNK_SIZE>, const CHUNK_SIZE: usize> HoldingPersistence<'a, M, P, S, CHUNK_SIZE> {
+    pub fn new(storage: &'a Mutex<M, P>, sector: S, start: u16, count: u16) -> Result<Self, Error> {
+        Self::new_advanced(storage, sector, start, count, Duration::from_millis(100), Duration::from_secs(2))
+    }
+
+    /// Registers are compared every `poll_period`, a change is written once they stayed unchanged for `settle_delay`
+    pub fn new_advanced(storage: &'a Mutex<M, P>, sector: S, start: u16, count: u16, poll_period: Duration, settle_delay: Duration) -> Result<Self, Error> {
+        if count as usize * 2 + 2 > CHUNK_SIZE {
+            return Err(Error::ChunkTooSmall);
+        }
+        Ok(Self {
+            storage,
+            sector,
+            start,
+            count,
+            poll_period,
+            settle_delay,
+            error_signal: None,
+        })
+    }
+
+    /// Flash errors of `run` are reported into `signal`
+    pub fn set_error_signal(&mut self, signal: &'a Signal<M, Error>) {
+        self.error_signal = Some(signal);
+    }
+
+    /// Load the stored registers, returns `false` if nothing was saved yet
+    pub async fn restore(&mut self) -> Result<bool, Error> {
+        let mut chunk = Chunk::new();
+        match self.sector.blocking_read(&mut chunk) {
+            Ok(()) => {},
+            Err(Error::NoData) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        let len = self.count as usize * 2;
+        if crc16(&chunk.data[..len]).to_le_bytes() != chunk.data[len..len + 2] {
+            return Err(Error::Checksum);
+        }
+        let mut storage = self.storage.lock().await;
+        for (reg, value) in (self.start..).zip(chunk.data[..len].chunks_exact(2)) {
+            storage.set_holding(reg, u16::from_be_bytes([value[0], value[1]])).map_err(|_| Error::Register(reg))?;
+        }
+        info!("HoldingPersistence: restored {} registers", self.count);
+        Ok(true)
+    }
+
+    /// Write the current registers to flash
+    pub async fn save(&mut self) -> Result<(), Error> {
+        let chunk = self.snapshot().await?;
+        self.sector.write(&chunk).await
+    }
+
+    /// Watch the registers and save them once changes settled. Failed writes are retried
+    /// with a doubling delay up to `MAX_RETRY_DELAY`. Never returns.
+    pub async fn run(&mut self) -> ! {
+        let mut retry_delay = self.settle_delay;
+        let mut saved = match self.snapshot().await {
+            Ok(chunk) => Some(chunk.data),
+            Err(e) => {
+                self.report(e);
+                None
+            },
+        };
+        loop {
+            Timer::after(self.poll_period).await;
+            let Ok(mut current) = self.snapshot().await else {
+                continue;
+            };
+            if saved.is_some_and(|s| s == current.data) {
+                continue;
+            }
+            // wait until the registers stop changing
+            loop {
+                Timer::after(self.settle_delay).await;
+                match self.snapshot().await {
+                    Ok(next) if next.data == current.data => break,
+                    Ok(next) => current = next,
+                    Err(_) => break,
+                }
+            }
+            match self.sector.write(&current).await {
+                Ok(()) => {
+                    saved = Some(current.data);
+                    retry_delay = self.settle_delay;
+                },
+                Err(e) => {
+                    self.report(e);
+                    Timer::after(retry_delay).await;
+                    retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                },
+            }
+        }
+    }
+
+    fn report(&self, error: Error) {
+        warn!("HoldingPersistence: {}", error);
+        if let Some(signal) = self.error_signal {
+            signal.signal(error);
+        }
+    }
+
+    async fn snapshot(&self) -> Result<Chunk<CHUNK_SIZE>, Error> {
+        let mut chunk = Chunk::new();
+        let len = self.count as usize * 2;
+        {
+            let storage = self.storage.lock().await;
+            for (reg, value) in (self.start..).zip(chunk.data[..len].chunks_exact_mut(2)) {
+                let holding = storage.holding(reg).map_err(|_| Error::Register(reg))?;
+                value.copy_from_slice(&holding.to_be_bytes());
+            }
+        }
+        let crc = crc16(&chunk.data[..len]).to_le_bytes();
+        chunk.data[len..len + 2].copy_from_slice(&crc);
+        Ok(chunk)
+    }
+}
@@ -1,3 +1,10 @@
 pub mod modbus_can_server;
+pub mod canopen_master;
+pub mod modbus_link;
+pub mod register_map;
+pub mod register_provider;
 pub mod modbus_server;
+#[cfg(feature = "net")]
+pub mod modbus_tcp_server;
+pub mod modbus_sniffer;
 pub mod modbus_master;
\ No newline at end of file
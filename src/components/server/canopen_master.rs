@@ -0,0 +1,295 @@
+use defmt::{info, trace, warn};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::can::{self, enums::{BusError, FrameCreateError}, CanRx, CanTx, Frame, StandardId};
+use embassy_time::{Duration, Instant, Timer};
+use sdo_client::{download_request, parse_download_response, parse_upload_response, upload_request, ByteOrder, SdoWrite, SDO_TX_BASE};
+
+pub mod sdo_client;
+
+const NMT_ID: u16 = 0x000;
+const HEARTBEAT_BASE: u16 = 0x700;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    BusError(BusError),
+    FrameCreate(FrameCreateError),
+    InvalidNodeId,
+    UnknownNode,
+    SdoTimeout,
+    SdoAbort(u32),
+    UnexpectedResponse,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NmtCommand {
+    Start = 0x01,
+    Stop = 0x02,
+    EnterPreOperational = 0x80,
+    ResetNode = 0x81,
+    ResetCommunication = 0x82,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NmtState {
+    BootUp,
+    Stopped,
+    Operational,
+    PreOperational,
+    Unknown(u8),
+}
+
+impl From<u8> for NmtState {
+    fn from(value: u8) -> Self {
+        match value & 0x7F {
+            0x00 => NmtState::BootUp,
+            0x04 => NmtState::Stopped,
+            0x05 => NmtState::Operational,
+            0x7F => NmtState::PreOperational,
+            v => NmtState::Unknown(v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlaveStatus {
+    /// Never heard from the slave
+    Missing,
+    Configuring,
+    /// Configuration list was rejected, slave is left in pre-operational
+    ConfigFailed,
+    Started,
+    /// Heartbeat timed out
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlaveConfig<'a> {
+    pub node_id: u8,
+    pub heartbeat_timeout: Duration,
+    /// SDO writes downloaded to the slave after every boot-up
+    pub dcf: &'a [SdoWrite],
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Slave<'a> {
+    config: SlaveConfig<'a>,
+    status: SlaveStatus,
+    /// Status restored when the heartbeat of a lost slave comes back
+    status_before_lost: SlaveStatus,
+    state: Option<NmtState>,
+    last_seen: Option<Instant>,
+    /// Boot-up received during an SDO transfer, configured by the next `update`
+    boot_pending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MasterEvent {
+    Started(u8),
+    ConfigFailed(u8),
+    HeartbeatLost(u8),
+    StateChanged(u8, NmtState),
+}
+
+/// NMT master and configuration manager for the slaves listed in `N` `SlaveConfig`s.
+pub struct CanOpenMaster<'a, const N: usize> {
+    can_tx: CanTx<'a>,
+    can_rx: CanRx<'a>,
+    slaves: [Slave<'a>; N],
+    byte_order: ByteOrder,
+    sdo_timeout: Duration,
+    check_period: Duration,
+}
+
+impl<'a, const N: usize> CanOpenMaster<'a, N> {
+    pub fn new(can_tx: CanTx<'a>, can_rx: CanRx<'a>, slaves: [SlaveConfig<'a>; N]) -> Self {
+        Self::new_advanced(can_tx, can_rx, slaves, ByteOrder::Little, Duration::from_millis(100), Duration::from_millis(100))
+    }
+
+    pub fn new_advanced(can_tx: CanTx<'a>, can_rx: CanRx<'a>, slaves: [SlaveConfig<'a>; N], byte_order: ByteOrder, sdo_timeout: Duration, check_period: Duration) -> Self {
+        Self {
+            can_tx,
+            can_rx,
+            slaves: slaves.map(|config| Slave {
+                config,
+                status: SlaveStatus::Missing,
+                status_before_lost: SlaveStatus::Missing,
+                state: None,
+                last_seen: None,
+                boot_pending: false,
+            }),
+            byte_order,
+            sdo_timeout,
+            check_period,
+        }
+    }
+
+    /// Reset communication of every node on the bus, slaves answer with boot-up and get configured
+    pub async fn start(&mut self) -> Result<(), Error> {
+        for slave in self.slaves.iter_mut() {
+            slave.status = SlaveStatus::Missing;
+            slave.state = None;
+            slave.last_seen = None;
+            slave.boot_pending = false;
+        }
+        self.nmt(NmtCommand::ResetCommunication, 0).await
+    }
+
+    pub async fn update(&mut self) -> Result<Option<MasterEvent>, Error> {
+        if let Some(slave) = self.slaves.iter_mut().find(|s| s.boot_pending) {
+            slave.boot_pending = false;
+            let node_id = slave.config.node_id;
+            info!("CanOpenMaster: node {} boot-up", node_id);
+            return Ok(Some(self.configure(node_id).await));
+        }
+        match select(self.can_rx.read(), Timer::after(self.check_period)).await {
+            Either::First(res) => {
+                let envelope = res.map_err(|e| Error::BusError(e))?;
+                if let Some((node_id, state)) = self.handle_heartbeat(&envelope.frame) {
+                    if state == NmtState::BootUp {
+                        info!("CanOpenMaster: node {} boot-up", node_id);
+                        return Ok(Some(self.configure(node_id).await));
+                    }
+                    return Ok(Some(MasterEvent::StateChanged(node_id, state)));
+                }
+            },
+            Either::Second(_) => {},
+        }
+        Ok(self.check_heartbeats())
+    }
+
+    pub async fn run(&mut self) -> ! {
+        loop {
+            match self.update().await {
+                Ok(Some(event)) => info!("CanOpenMaster: {}", event),
+                Ok(None) => {},
+                Err(e) => warn!("CanOpenMaster: {}", e),
+            }
+        }
+    }
+
+    /// Returns node id and state if the frame is a heartbeat of a known slave whose state changed
+    fn handle_heartbeat(&mut self, frame: &Frame) -> Option<(u8, NmtState)> {
+        let can::Id::Standard(id) = frame.id() else {
+            return None;
+        };
+        let raw = id.as_raw();
+        if !(HEARTBEAT_BASE + 1..=HEARTBEAT_BASE + 0x7F).contains(&raw) || frame.data().is_empty() {
+            return None;
+        }
+        let node_id = (raw - HEARTBEAT_BASE) as u8;
+        let slave = self.slaves.iter_mut().find(|s| s.config.node_id == node_id)?;
+        let state = NmtState::from(frame.data()[0]);
+        slave.last_seen = Some(Instant::now());
+        if slave.status == SlaveStatus::Lost {
+            slave.status = slave.status_before_lost;
+        }
+        if slave.state == Some(state) {
+            return None;
+        }
+        trace!("CanOpenMaster: node {} state {}", node_id, state);
+        slave.state = Some(state);
+        Some((node_id, state))
+    }
+
+    fn check_heartbeats(&mut self) -> Option<MasterEvent> {
+        let now = Instant::now();
+        for slave in self.slaves.iter_mut() {
+            if slave.status == SlaveStatus::Lost || slave.config.heartbeat_timeout == Duration::from_ticks(0) {
+                continue;
+            }
+            if let Some(last_seen) = slave.last_seen {
+                if now - last_seen > slave.config.heartbeat_timeout {
+                    slave.status_before_lost = slave.status;
+                    slave.status = SlaveStatus::Lost;
+                    slave.state = None;
+                    return Some(MasterEvent::HeartbeatLost(slave.config.node_id));
+                }
+            }
+        }
+        None
+    }
+
+    async fn configure(&mut self, node_id: u8) -> MasterEvent {
+        let Some(index) = self.slaves.iter().position(|s| s.config.node_id == node_id) else {
+            return MasterEvent::ConfigFailed(node_id);
+        };
+        self.slaves[index].status = SlaveStatus::Configuring;
+        let dcf = self.slaves[index].config.dcf;
+        for write in dcf {
+            if let Err(e) = self.sdo_write(node_id, write).await {
+                warn!("CanOpenMaster: node {} config {:x}:{} failed {}", node_id, write.index, write.sub_index, e);
+                self.slaves[index].status = SlaveStatus::ConfigFailed;
+                return MasterEvent::ConfigFailed(node_id);
+            }
+        }
+        if let Err(e) = self.nmt(NmtCommand::Start, node_id).await {
+            warn!("CanOpenMaster: node {} start failed {}", node_id, e);
+            self.slaves[index].status = SlaveStatus::ConfigFailed;
+            return MasterEvent::ConfigFailed(node_id);
+        }
+        self.slaves[index].status = SlaveStatus::Started;
+        MasterEvent::Started(node_id)
+    }
+
+    /// Send NMT command to `node_id`, `0` addresses all nodes
+    pub async fn nmt(&mut self, command: NmtCommand, node_id: u8) -> Result<(), Error> {
+        let frame = Frame::new_data(StandardId::new(NMT_ID).ok_or(Error::InvalidNodeId)?, &[command as u8, node_id])
+            .map_err(|e| Error::FrameCreate(e))?;
+        self.can_tx.write(&frame).await;
+        Ok(())
+    }
+
+    pub async fn sdo_write(&mut self, node_id: u8, write: &SdoWrite) -> Result<(), Error> {
+        let request = download_request(node_id, self.byte_order, write)?;
+        let response = self.sdo_transfer(node_id, &request).await?;
+        parse_download_response(self.byte_order, write, &response)
+    }
+
+    pub async fn sdo_read(&mut self, node_id: u8, index: u16, sub_index: u8) -> Result<u32, Error> {
+        let request = upload_request(node_id, self.byte_order, index, sub_index)?;
+        let response = self.sdo_transfer(node_id, &request).await?;
+        parse_upload_response(self.byte_order, index, sub_index, &response)
+    }
+
+    async fn sdo_transfer(&mut self, node_id: u8, request: &Frame) -> Result<[u8; 8], Error> {
+        self.can_tx.write(request).await;
+        let deadline = Instant::now() + self.sdo_timeout;
+        loop {
+            match select(self.can_rx.read(), Timer::at(deadline)).await {
+                Either::First(res) => {
+                    let envelope = res.map_err(|e| Error::BusError(e))?;
+                    match envelope.frame.id() {
+                        can::Id::Standard(id) if id.as_raw() == SDO_TX_BASE + node_id as u16 => {
+                            let mut data = [0u8; 8];
+                            let len = envelope.frame.data().len().min(8);
+                            data[..len].copy_from_slice(&envelope.frame.data()[..len]);
+                            return Ok(data);
+                        },
+                        _ => {
+                            // keep tracking heartbeats while waiting for the answer
+                            if let Some((id, NmtState::BootUp)) = self.handle_heartbeat(&envelope.frame) {
+                                if let Some(slave) = self.slaves.iter_mut().find(|s| s.config.node_id == id) {
+                                    slave.boot_pending = true;
+                                }
+                            }
+                        },
+                    }
+                },
+                Either::Second(_) => return Err(Error::SdoTimeout),
+            }
+        }
+    }
+
+    pub fn slave_status(&self, node_id: u8) -> Result<SlaveStatus, Error> {
+        self.slaves.iter().find(|s| s.config.node_id == node_id).map(|s| s.status).ok_or(Error::UnknownNode)
+    }
+
+    pub fn slave_state(&self, node_id: u8) -> Result<Option<NmtState>, Error> {
+        self.slaves.iter().find(|s| s.config.node_id == node_id).map(|s| s.state).ok_or(Error::UnknownNode)
+    }
+}
@@ -0,0 +1,139 @@
+use embassy_stm32::can::{Frame, StandardId};
+
+use super::Error;
+
+const SDO_RX_BASE: u16 = 0x600;
+pub(crate) const SDO_TX_BASE: u16 = 0x580;
+
+const CMD_DOWNLOAD_1B: u8 = 0x2F;
+const CMD_DOWNLOAD_2B: u8 = 0x2B;
+const CMD_DOWNLOAD_4B: u8 = 0x23;
+const CMD_UPLOAD: u8 = 0x40;
+const RESPONSE_DOWNLOAD: u8 = 0x60;
+const RESPONSE_ABORT: u8 = 0x80;
+
+/// Byte order of index and data in SDO frames.
+/// `Little` is CiA 301, `Big` is what `CanServer` speaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ByteOrder {
+    Little,
+    Big,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SdoValue {
+    U8(u8),
+    U16(u16),
+    U32(u32),
+}
+
+impl SdoValue {
+    fn command(&self) -> u8 {
+        match self {
+            SdoValue::U8(_) => CMD_DOWNLOAD_1B,
+            SdoValue::U16(_) => CMD_DOWNLOAD_2B,
+            SdoValue::U32(_) => CMD_DOWNLOAD_4B,
+        }
+    }
+
+    fn write_bytes(&self, order: ByteOrder, buf: &mut [u8]) {
+        match (self, order) {
+            (SdoValue::U8(v), _) => buf[0] = *v,
+            (SdoValue::U16(v), ByteOrder::Little) => buf[..2].copy_from_slice(&v.to_le_bytes()),
+            (SdoValue::U16(v), ByteOrder::Big) => buf[..2].copy_from_slice(&v.to_be_bytes()),
+            (SdoValue::U32(v), ByteOrder::Little) => buf.copy_from_slice(&v.to_le_bytes()),
+            (SdoValue::U32(v), ByteOrder::Big) => buf.copy_from_slice(&v.to_be_bytes()),
+        }
+    }
+}
+
+/// One entry of a slave configuration list (concise DCF)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SdoWrite {
+    pub index: u16,
+    pub sub_index: u8,
+    pub value: SdoValue,
+}
+
+impl SdoWrite {
+    pub const fn new(index: u16, sub_index: u8, value: SdoValue) -> Self {
+        Self { index, sub_index, value }
+    }
+}
+
+fn write_index(order: ByteOrder, index: u16, sub_index: u8, buf: &mut [u8; 8]) {
+    match order {
+        ByteOrder::Little => buf[1..3].copy_from_slice(&index.to_le_bytes()),
+        ByteOrder::Big => buf[1..3].copy_from_slice(&index.to_be_bytes()),
+    }
+    buf[3] = sub_index;
+}
+
+fn new_request_frame(node_id: u8, data: &[u8; 8]) -> Result<Frame, Error> {
+    let id = StandardId::new(SDO_RX_BASE + node_id as u16).ok_or(Error::InvalidNodeId)?;
+    Frame::new_data(id, data).map_err(|e| Error::FrameCreate(e))
+}
+
+pub fn download_request(node_id: u8, order: ByteOrder, write: &SdoWrite) -> Result<Frame, Error> {
+    let mut data = [0u8; 8];
+    data[0] = write.value.command();
+    write_index(order, write.index, write.sub_index, &mut data);
+    write.value.write_bytes(order, &mut data[4..]);
+    new_request_frame(node_id, &data)
+}
+
+pub fn upload_request(node_id: u8, order: ByteOrder, index: u16, sub_index: u8) -> Result<Frame, Error> {
+    let mut data = [0u8; 8];
+    data[0] = CMD_UPLOAD;
+    write_index(order, index, sub_index, &mut data);
+    new_request_frame(node_id, &data)
+}
+
+fn check_response(order: ByteOrder, index: u16, sub_index: u8, data: &[u8]) -> Result<(), Error> {
+    if data.len() < 8 {
+        return Err(Error::UnexpectedResponse);
+    }
+    if data[0] == RESPONSE_ABORT {
+        let code = match order {
+            ByteOrder::Little => u32::from_le_bytes([data[4], data[5], data[6], data[7]]),
+            ByteOrder::Big => u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        };
+        return Err(Error::SdoAbort(code));
+    }
+    let mut expected = [0u8; 8];
+    write_index(order, index, sub_index, &mut expected);
+    if data[1..4] != expected[1..4] {
+        return Err(Error::UnexpectedResponse);
+    }
+    Ok(())
+}
+
+pub fn parse_download_response(order: ByteOrder, write: &SdoWrite, data: &[u8]) -> Result<(), Error> {
+    check_response(order, write.index, write.sub_index, data)?;
+    if data[0] != RESPONSE_DOWNLOAD {
+        return Err(Error::UnexpectedResponse);
+    }
+    Ok(())
+}
+
+/// Parse an expedited upload response, value is zero extended to u32
+pub fn parse_upload_response(order: ByteOrder, index: u16, sub_index: u8, data: &[u8]) -> Result<u32, Error> {
+    check_response(order, index, sub_index, data)?;
+    // expedited transfer bit set, size bits tell how many bytes are unused
+    if data[0] & 0xE2 != 0x42 {
+        return Err(Error::UnexpectedResponse);
+    }
+    let size = if data[0] & 0x01 != 0 { 4 - ((data[0] >> 2) & 0x03) as usize } else { 4 };
+    let mut bytes = [0u8; 4];
+    match order {
+        ByteOrder::Little => bytes[..size].copy_from_slice(&data[4..4 + size]),
+        ByteOrder::Big => bytes[4 - size..].copy_from_slice(&data[4..4 + size]),
+    }
+    Ok(match order {
+        ByteOrder::Little => u32::from_le_bytes(bytes),
+        ByteOrder::Big => u32::from_be_bytes(bytes),
+    })
+}
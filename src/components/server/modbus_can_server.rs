@@ -4,10 +4,17 @@ use embassy_stm32::can::{self, enums::{BusError, FrameCreateError}, CanRx, CanTx
 use embassy_sync::{blocking_mutex::raw::RawMutex, channel::{self}, mutex::Mutex};
 use pdo::{RPDO, TPDO};
 use rmodbus::server::storage::ModbusStorage;
-use sdo::{create_not_implemented_response, create_sdo_abort_response, handle_read_command, handle_unknown_command, handle_write_command, SdoCmd};
+
+use super::register_map::RegisterMap;
+use od::ObjectDictionary;
+use sdo::{check_object_access, create_not_implemented_response, create_sdo_abort_response, handle_read_command, handle_trace_read_command, handle_unknown_command, handle_write_command, SdoCmd, SubIndex};
+use trace::{Direction, FrameTrace};
 
 mod sdo;
 pub mod pdo;
+pub mod trace;
+pub mod od;
+pub mod eds;
 
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -25,13 +32,16 @@ pub struct CanServer<'a, const C: usize, const D: usize, const I: usize, const H
     can_tx: CanTx<'a>, 
     can_rx: CanRx<'a>, 
     storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>,
-    tx_pdo_channel: channel::Receiver<'a, M, TPDO, CS>
+    tx_pdo_channel: channel::Receiver<'a, M, TPDO, CS>,
+    trace: Option<&'a dyn FrameTrace>,
+    od: Option<&'a ObjectDictionary>,
+    register_map: Option<&'a RegisterMap<'a>>,
 }
 
 impl<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex, const CS: usize> CanServer<'a, C, D, I, H, M, CS> {
     pub fn new(node_id: u8, can_tx: CanTx<'a>, can_rx: CanRx<'a>, tx_pdo_channel: channel::Receiver<'a, M, TPDO, CS>, storage: &'static Mutex<M, ModbusStorage<C, D, I, H>>) -> Self {
         Self {
-            node_id, can_tx, can_rx, storage, tx_pdo_channel
+            node_id, can_tx, can_rx, storage, tx_pdo_channel, trace: None, od: None, register_map: None
         }
     }
 
@@ -39,6 +49,9 @@ impl<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawM
         match select(self.can_rx.read(), self.tx_pdo_channel.receive()).await {
             embassy_futures::select::Either::First(res) => {
                 let envelope = res.map_err(|e| Error::BusError(e))?;
+                if let Some(trace) = self.trace {
+                    trace.record(Direction::Rx, &envelope.frame);
+                }
                 match envelope.frame.id() {
                     can::Id::Standard(id) => {
                         if id.as_raw() == 0x600 + self.node_id as u16 {
@@ -59,7 +72,7 @@ impl<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawM
                 };
             },
             embassy_futures::select::Either::Second(tpdo) => {
-                self.can_tx.write(&tpdo.frame(self.node_id)).await;
+                self.transmit(&tpdo.frame(self.node_id)).await;
             },
         };
         
@@ -68,18 +81,15 @@ impl<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawM
 
     async fn process_sdo(&mut self, node_id: u8, data: &[u8]) {
         let cmd = SdoCmd::from(data[0]);
-        let res = match cmd {
-            SdoCmd::Unknown => handle_unknown_command(data, node_id),
-            SdoCmd::ReadAny => handle_read_command(cmd, data, node_id, self.storage).await,
-            SdoCmd::Read2b => handle_read_command(cmd, data, node_id, self.storage).await,
-            SdoCmd::Read4b => handle_read_command(cmd, data, node_id, self.storage).await,
-            SdoCmd::Write2b => handle_write_command(cmd, data, node_id, self.storage).await,
-            SdoCmd::Write4b => handle_write_command(cmd, data, node_id, self.storage).await,
-            _ => create_not_implemented_response(data, node_id).await,
+        let res = match (cmd, self.trace) {
+            (SdoCmd::ReadAny | SdoCmd::Read4b, Some(trace)) if data.len() >= 4 && SubIndex::from(data[3]) == SubIndex::Trace => {
+                handle_trace_read_command(cmd, data, node_id, trace)
+            },
+            _ => self.process_storage_sdo(cmd, node_id, data).await,
         };
         match res {
             Ok(frame) => {
-                self.can_tx.write(&frame).await;
+                self.transmit(&frame).await;
             },
             Err(sdo::Error::SdoAbort(e)) => {
                 if let Err(e) = create_sdo_abort_response(data, node_id, e).await {
@@ -92,6 +102,43 @@ impl<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawM
         }
     }
 
+    async fn process_storage_sdo(&self, cmd: SdoCmd, node_id: u8, data: &[u8]) -> Result<Frame, sdo::Error> {
+        if let Some(od) = self.od {
+            check_object_access(cmd, data, od)?;
+        }
+        match cmd {
+            SdoCmd::Unknown => handle_unknown_command(data, node_id),
+            SdoCmd::ReadAny => handle_read_command(cmd, data, node_id, self.storage).await,
+            SdoCmd::Read2b => handle_read_command(cmd, data, node_id, self.storage).await,
+            SdoCmd::Read4b => handle_read_command(cmd, data, node_id, self.storage).await,
+            SdoCmd::Write2b => handle_write_command(cmd, data, node_id, self.storage, self.register_map).await,
+            SdoCmd::Write4b => handle_write_command(cmd, data, node_id, self.storage, self.register_map).await,
+            _ => create_not_implemented_response(data, node_id).await,
+        }
+    }
+
+    async fn transmit(&mut self, frame: &Frame) {
+        if let Some(trace) = self.trace {
+            trace.record(Direction::Tx, frame);
+        }
+        self.can_tx.write(frame).await;
+    }
+
+    /// Record every received and transmitted frame into `trace`
+    pub fn set_trace(&mut self, trace: &'a dyn FrameTrace) {
+        self.trace = Some(trace);
+    }
+
+    /// Serve only the objects described in `od`, the same description is used to generate the EDS
+    pub fn set_object_dictionary(&mut self, od: &'a ObjectDictionary) {
+        self.od = Some(od);
+    }
+
+    /// Check SDO writes against the same register map as the Modbus server
+    pub fn set_register_map(&mut self, register_map: &'a RegisterMap<'a>) {
+        self.register_map = Some(register_map);
+    }
+
     pub fn set_node_id(&mut self, node_id: u8) {
         self.node_id = node_id;
     }
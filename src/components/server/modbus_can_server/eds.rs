@@ -0,0 +1,212 @@
+//! EDS (CiA 306) generator for the objects served by `CanServer`, covered by the host tests in `host-tests`.
+
+use core::fmt::{self, Display, Write};
+
+use super::od::{AccessType, DataType, ObjectDictionary, PdoMapping, OD_BASE_INDEX, OD_MAX_REGISTER};
+
+const OBJECT_TYPE_VAR: u8 = 0x7;
+const OBJECT_TYPE_RECORD: u8 = 0x9;
+const UNSIGNED8: u16 = 0x0005;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    Fmt,
+    /// Register above `OD_MAX_REGISTER`, its object index would leave the manufacturer range
+    IndexOutOfRange(u16),
+}
+
+impl From<fmt::Error> for Error {
+    fn from(_: fmt::Error) -> Self {
+        Error::Fmt
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EdsInfo<'a> {
+    pub file_name: &'a str,
+    pub file_version: u8,
+    pub description: &'a str,
+    pub created_by: &'a str,
+    pub vendor_name: &'a str,
+    pub vendor_number: u32,
+    pub product_name: &'a str,
+    pub product_number: u32,
+    pub revision_number: u32,
+    pub order_code: &'a str,
+    pub device_type: u32,
+    /// Supported bit rates in kbit/s
+    pub baud_rates: &'a [u16],
+}
+
+struct Section {
+    index: u16,
+    sub_index: Option<u8>,
+}
+
+impl Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.sub_index {
+            Some(sub) => write!(f, "[{:04X}sub{:X}]", self.index, sub),
+            None => write!(f, "[{:04X}]", self.index),
+        }
+    }
+}
+
+fn write_record<W: Write>(w: &mut W, index: u16, name: &str, sub_number: usize) -> fmt::Result {
+    writeln!(w, "{}", Section { index, sub_index: None })?;
+    writeln!(w, "ParameterName={}", name)?;
+    writeln!(w, "ObjectType=0x{:X}", OBJECT_TYPE_RECORD)?;
+    writeln!(w, "SubNumber=0x{:X}", sub_number)?;
+    writeln!(w)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_var<W: Write>(w: &mut W, index: u16, sub_index: Option<u8>, name: &str, data_type: u16, access: AccessType, default: impl Display, pdo_mapping: bool) -> fmt::Result {
+    writeln!(w, "{}", Section { index, sub_index })?;
+    writeln!(w, "ParameterName={}", name)?;
+    writeln!(w, "ObjectType=0x{:X}", OBJECT_TYPE_VAR)?;
+    writeln!(w, "DataType=0x{:04X}", data_type)?;
+    writeln!(w, "AccessType={}", access.as_str())?;
+    writeln!(w, "DefaultValue={}", default)?;
+    writeln!(w, "PDOMapping={}", pdo_mapping as u8)?;
+    writeln!(w)
+}
+
+struct Hex(u32);
+
+impl Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
+    }
+}
+
+struct NodeId(u16);
+
+impl Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "$NODEID+0x{:X}", self.0)
+    }
+}
+
+fn write_pdo<W: Write>(w: &mut W, comm_index: u16, mapping_index: u16, name: &str, cob_base: u16, pdo: &PdoMapping) -> fmt::Result {
+    write_record(w, comm_index, name, 3)?;
+    write_var(w, comm_index, Some(0), "Highest sub-index supported", UNSIGNED8, AccessType::Const, Hex(2), false)?;
+    write_var(w, comm_index, Some(1), "COB-ID", DataType::Unsigned32 as u16, AccessType::Const, NodeId(cob_base), false)?;
+    write_var(w, comm_index, Some(2), "Transmission type", UNSIGNED8, AccessType::Const, Hex(0xFF), false)?;
+
+    write_record(w, mapping_index, name, pdo.objects.len() + 1)?;
+    write_var(w, mapping_index, Some(0), "Number of mapped objects", UNSIGNED8, AccessType::Const, Hex(pdo.objects.len() as u32), false)?;
+    for (i, o) in pdo.objects.iter().enumerate() {
+        let value = ((OD_BASE_INDEX + o.index) as u32) << 16 | (o.sub_index as u32) << 8 | o.bits as u32;
+        write_var(w, mapping_index, Some(i as u8 + 1), "Mapped object", DataType::Unsigned32 as u16, AccessType::Const, Hex(value), false)?;
+    }
+    Ok(())
+}
+
+fn write_object_list<W: Write>(w: &mut W, section: &str, indexes: impl Iterator<Item = u16> + Clone) -> fmt::Result {
+    writeln!(w, "[{}]", section)?;
+    writeln!(w, "SupportedObjects={}", indexes.clone().count())?;
+    for (i, index) in indexes.enumerate() {
+        writeln!(w, "{}=0x{:04X}", i + 1, index)?;
+    }
+    writeln!(w)
+}
+
+/// Unique registers of the dictionary in order of first appearance
+fn registers(od: &ObjectDictionary) -> impl Iterator<Item = u16> + Clone + '_ {
+    od.entries
+        .iter()
+        .enumerate()
+        .filter(|(i, e)| !od.entries[..*i].iter().any(|p| p.index == e.index))
+        .map(|(_, e)| e.index)
+}
+
+/// Registers are described at `OD_BASE_INDEX + register`. Objects with a single entry at
+/// sub-index 0 are written as VAR, all others as RECORD. Sub-index 0 of a record holds
+/// "Highest sub-index supported", unless the register has a coil there (sub-index = area).
+pub fn write_eds<W: Write>(w: &mut W, info: &EdsInfo, od: &ObjectDictionary) -> Result<(), Error> {
+    let mapped = od.rpdos.iter().chain(od.tpdos).flat_map(|p| p.objects.iter().map(|o| o.index));
+    if let Some(reg) = registers(od).chain(mapped).find(|&reg| reg > OD_MAX_REGISTER) {
+        return Err(Error::IndexOutOfRange(reg));
+    }
+
+    writeln!(w, "[FileInfo]")?;
+    writeln!(w, "FileName={}", info.file_name)?;
+    writeln!(w, "FileVersion={}", info.file_version)?;
+    writeln!(w, "FileRevision=0")?;
+    writeln!(w, "EDSVersion=4.0")?;
+    writeln!(w, "Description={}", info.description)?;
+    writeln!(w, "CreatedBy={}", info.created_by)?;
+    writeln!(w)?;
+
+    writeln!(w, "[DeviceInfo]")?;
+    writeln!(w, "VendorName={}", info.vendor_name)?;
+    writeln!(w, "VendorNumber=0x{:X}", info.vendor_number)?;
+    writeln!(w, "ProductName={}", info.product_name)?;
+    writeln!(w, "ProductNumber=0x{:X}", info.product_number)?;
+    writeln!(w, "RevisionNumber=0x{:X}", info.revision_number)?;
+    writeln!(w, "OrderCode={}", info.order_code)?;
+    for rate in [10u16, 20, 50, 125, 250, 500, 800, 1000] {
+        writeln!(w, "BaudRate_{}={}", rate, info.baud_rates.contains(&rate) as u8)?;
+    }
+    writeln!(w, "SimpleBootUpMaster=0")?;
+    writeln!(w, "SimpleBootUpSlave=1")?;
+    writeln!(w, "Granularity=8")?;
+    writeln!(w, "DynamicChannelsSupported=0")?;
+    writeln!(w, "GroupMessaging=0")?;
+    writeln!(w, "NrOfRXPDO={}", od.rpdos.len())?;
+    writeln!(w, "NrOfTXPDO={}", od.tpdos.len())?;
+    writeln!(w, "LSS_Supported=0")?;
+    writeln!(w)?;
+
+    writeln!(w, "[DummyUsage]")?;
+    for i in 1..=7 {
+        writeln!(w, "Dummy{:04X}=0", i)?;
+    }
+    writeln!(w)?;
+
+    write_object_list(w, "MandatoryObjects", [0x1000u16, 0x1001, 0x1018].into_iter())?;
+    write_var(w, 0x1000, None, "Device type", DataType::Unsigned32 as u16, AccessType::Const, Hex(info.device_type), false)?;
+    write_var(w, 0x1001, None, "Error register", UNSIGNED8, AccessType::Ro, Hex(0), false)?;
+    write_record(w, 0x1018, "Identity object", 4)?;
+    write_var(w, 0x1018, Some(0), "Highest sub-index supported", UNSIGNED8, AccessType::Const, Hex(3), false)?;
+    write_var(w, 0x1018, Some(1), "Vendor-ID", DataType::Unsigned32 as u16, AccessType::Const, Hex(info.vendor_number), false)?;
+    write_var(w, 0x1018, Some(2), "Product code", DataType::Unsigned32 as u16, AccessType::Const, Hex(info.product_number), false)?;
+    write_var(w, 0x1018, Some(3), "Revision number", DataType::Unsigned32 as u16, AccessType::Const, Hex(info.revision_number), false)?;
+
+    let optional = od.rpdos.iter().map(|p| 0x1400 + p.number as u16)
+        .chain(od.rpdos.iter().map(|p| 0x1600 + p.number as u16))
+        .chain(od.tpdos.iter().map(|p| 0x1800 + p.number as u16))
+        .chain(od.tpdos.iter().map(|p| 0x1A00 + p.number as u16));
+    write_object_list(w, "OptionalObjects", optional)?;
+    for pdo in od.rpdos {
+        write_pdo(w, 0x1400 + pdo.number as u16, 0x1600 + pdo.number as u16, "RPDO", pdo.rpdo_cob_base(), pdo)?;
+    }
+    for pdo in od.tpdos {
+        write_pdo(w, 0x1800 + pdo.number as u16, 0x1A00 + pdo.number as u16, "TPDO", pdo.tpdo_cob_base(), pdo)?;
+    }
+
+    write_object_list(w, "ManufacturerObjects", registers(od).map(|reg| OD_BASE_INDEX + reg))?;
+    for reg in registers(od) {
+        let index = OD_BASE_INDEX + reg;
+        let subs = od.entries.iter().filter(|e| e.index == reg);
+        let first = subs.clone().next().map_or("", |e| e.name);
+        let coil = subs.clone().find(|e| e.sub_index == 0);
+        if let (1, Some(e)) = (subs.clone().count(), coil) {
+            write_var(w, index, None, first, e.data_type as u16, e.access, Hex(e.default), e.pdo_mappable)?;
+            continue;
+        }
+        if coil.is_some() {
+            write_record(w, index, first, subs.clone().count())?;
+        } else {
+            let highest = subs.clone().map(|e| e.sub_index).max().unwrap_or(0);
+            write_record(w, index, first, subs.clone().count() + 1)?;
+            write_var(w, index, Some(0), "Highest sub-index supported", UNSIGNED8, AccessType::Const, Hex(highest as u32), false)?;
+        }
+        for e in subs {
+            write_var(w, index, Some(e.sub_index), e.name, e.data_type as u16, e.access, Hex(e.default), e.pdo_mappable)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+/// CANopen data types, value is the EDS `DataType` code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DataType {
+    Boolean = 0x0001,
+    Integer16 = 0x0003,
+    Integer32 = 0x0004,
+    Unsigned16 = 0x0006,
+    Unsigned32 = 0x0007,
+    Real32 = 0x0008,
+}
+
+impl DataType {
+    pub const fn bits(&self) -> u8 {
+        match self {
+            DataType::Boolean => 1,
+            DataType::Integer16 | DataType::Unsigned16 => 16,
+            DataType::Integer32 | DataType::Unsigned32 | DataType::Real32 => 32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AccessType {
+    Ro,
+    Wo,
+    Rw,
+    Const,
+}
+
+impl AccessType {
+    pub const fn readable(&self) -> bool {
+        !matches!(self, AccessType::Wo)
+    }
+
+    pub const fn writable(&self) -> bool {
+        matches!(self, AccessType::Wo | AccessType::Rw)
+    }
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            AccessType::Ro => "ro",
+            AccessType::Wo => "wo",
+            AccessType::Rw => "rw",
+            AccessType::Const => "const",
+        }
+    }
+}
+
+/// Registers are served and described at object index `OD_BASE_INDEX + register`
+pub const OD_BASE_INDEX: u16 = 0x2000;
+/// Highest register reachable below the end of the manufacturer range 0x5FFF
+pub const OD_MAX_REGISTER: u16 = 0x3FFF;
+
+/// Object served by `CanServer`. `index` is the storage register, `sub_index` the storage area (see `SubIndex`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OdEntry {
+    pub index: u16,
+    pub sub_index: u8,
+    pub name: &'static str,
+    pub data_type: DataType,
+    pub access: AccessType,
+    pub default: u32,
+    pub pdo_mappable: bool,
+}
+
+impl OdEntry {
+    pub const fn new(index: u16, sub_index: u8, name: &'static str, data_type: DataType, access: AccessType) -> Self {
+        Self { index, sub_index, name, data_type, access, default: 0, pdo_mappable: false }
+    }
+
+    pub const fn with_default(mut self, default: u32) -> Self {
+        self.default = default;
+        self
+    }
+
+    pub const fn mappable(mut self) -> Self {
+        self.pdo_mappable = true;
+        self
+    }
+}
+
+/// `index` is the storage register, like `OdEntry::index`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PdoMappedObject {
+    pub index: u16,
+    pub sub_index: u8,
+    pub bits: u8,
+}
+
+/// PDO content, `number` is the same one passed to `TPDO::new` or received as `RPDO::RPDOx`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PdoMapping {
+    pub number: u8,
+    pub objects: &'static [PdoMappedObject],
+}
+
+impl PdoMapping {
+    /// COB-ID offset added to node id, matches `TPDO::frame`
+    pub const fn tpdo_cob_base(&self) -> u16 {
+        let number = self.number as u16;
+        (number / 4) + 0x180 + (number % 4) * 0x100
+    }
+
+    /// COB-ID offset added to node id, matches `CanServer::update`
+    pub const fn rpdo_cob_base(&self) -> u16 {
+        0x200 + (self.number as u16 % 4) * 0x100
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectDictionary {
+    pub entries: &'static [OdEntry],
+    pub tpdos: &'static [PdoMapping],
+    pub rpdos: &'static [PdoMapping],
+}
+
+impl ObjectDictionary {
+    pub fn find(&self, index: u16, sub_index: u8) -> Option<&OdEntry> {
+        self.entries.iter().find(|e| e.index == index && e.sub_index == sub_index)
+    }
+}
@@ -4,6 +4,10 @@ use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
 use heapless::Vec;
 use rmodbus::server::{context::ModbusContext, storage::ModbusStorage};
 
+use crate::components::server::register_map::{Area, Exception, RegisterMap};
+use super::od::{ObjectDictionary, OD_BASE_INDEX};
+use super::trace::FrameTrace;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SdoCmd {
@@ -93,6 +97,8 @@ pub enum SdoAbortCode {
     InvalidSubindex,
     ReadError,
     InvalidData,
+    NoObject,
+    AccessDenied,
     NotImplemented,
     Unknown(u32), // Fallback for unknown codes
 }
@@ -105,6 +111,8 @@ impl From<u32> for SdoAbortCode {
             0x0000_0003 => SdoAbortCode::InvalidSubindex,
             0x0000_0004 => SdoAbortCode::ReadError,
             0x0000_0005 => SdoAbortCode::InvalidData,
+            0x0000_0006 => SdoAbortCode::NoObject,
+            0x0000_0007 => SdoAbortCode::AccessDenied,
             0x0000_00ff => SdoAbortCode::NotImplemented,
             unknown => SdoAbortCode::Unknown(unknown),
         }
@@ -119,6 +127,8 @@ impl From<SdoAbortCode> for u32 {
             SdoAbortCode::InvalidSubindex => 0x0000_0003,
             SdoAbortCode::ReadError => 0x0000_0004, // Keeps unknown codes as they are
             SdoAbortCode::InvalidData => 0x0000_0005, // Keeps unknown codes as they are
+            SdoAbortCode::NoObject => 0x0000_0006,
+            SdoAbortCode::AccessDenied => 0x0000_0007,
             SdoAbortCode::NotImplemented => 0x0000_00ff,
             SdoAbortCode::Unknown(unknown_code) => unknown_code,
         }
@@ -143,6 +153,8 @@ pub(crate) enum Error {
 #[allow(unused)] const DATA: usize = 4;
 #[allow(unused)] const DATA_END: usize = 7;
 
+const TRACE_COUNT_INDEX: u16 = 0xFFFF;
+
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -151,6 +163,7 @@ pub enum SubIndex {
     Discrete,
     Holding,
     Input,
+    Trace,
     Unknown(u8)
 }
 
@@ -161,12 +174,34 @@ impl From<u8> for SubIndex {
             1 => Self::Discrete,
             2 => Self::Holding,
             3 => Self::Input, 
+            0x10 => Self::Trace,
             _ => Self::Unknown(value) 
         }
     }
 }
 
 
+/// Reject access to objects missing from `od` or not allowed by their access type
+pub(crate) fn check_object_access(cmd: SdoCmd, data: &[u8], od: &ObjectDictionary) -> Result<(), Error> {
+    check_header_data(data).map_err(|_| Error::SdoAbort(SdoAbortCode::InvalidQuery))?;
+    let reg = register(data).map_err(|e| Error::SdoAbort(e))?;
+    let entry = od.find(reg, data[SUB_INDEX]).ok_or(Error::SdoAbort(SdoAbortCode::NoObject))?;
+    let allowed = match cmd {
+        SdoCmd::ReadAny | SdoCmd::Read1B | SdoCmd::Read2b | SdoCmd::Read4b => entry.access.readable(),
+        SdoCmd::Write1B | SdoCmd::Write2b | SdoCmd::Write4b => entry.access.writable(),
+        SdoCmd::Unknown => true,
+    };
+    if !allowed {
+        return Err(Error::SdoAbort(SdoAbortCode::AccessDenied));
+    }
+    Ok(())
+}
+
+/// Storage register addressed by the object index of the request
+fn register(data: &[u8]) -> Result<u16, SdoAbortCode> {
+    u16::from_be_bytes([data[INDEX], data[INDEX_END]]).checked_sub(OD_BASE_INDEX).ok_or(SdoAbortCode::NoObject)
+}
+
 fn check_header_data(data: &[u8]) -> Result<(), Error> {
     if data.len() < 4 {
         return Err(Error::NotEnoughData)
@@ -215,7 +250,31 @@ pub(crate) async fn handle_read_command<'a, const C: usize, const D: usize, cons
     return new_data_frame(node_id, response_data.as_slice());
 }
 
-pub(crate) async fn handle_write_command<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex>(cmd: SdoCmd, data: &[u8], node_id: u8, storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>) -> Result<Frame, Error> {
+/// Trace read back: index `0xFFFF` returns entries count, otherwise index is `entry << 3 | word`
+pub(crate) fn handle_trace_read_command(cmd: SdoCmd, data: &[u8], node_id: u8, trace: &dyn FrameTrace) -> Result<Frame, Error> {
+    let mut response_data = Vec::<u8, 8>::new();
+
+    check_header_data(data).map_err(|_| Error::SdoAbort(SdoAbortCode::InvalidQuery))?;
+    match cmd {
+        SdoCmd::ReadAny | SdoCmd::Read4b => {},
+        _ => return Err(Error::SdoAbort(SdoAbortCode::InvalidQuery)),
+    }
+    response_data.extend_from_slice(&data[CMD..DATA]).map_err(|_| Error::VectorError)?;
+
+    let index = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+    let v = if index == TRACE_COUNT_INDEX {
+        trace.len() as u32
+    } else {
+        trace.entry((index >> 3) as usize)
+            .and_then(|e| e.word((index & 0x7) as u8))
+            .ok_or(Error::SdoAbort(SdoAbortCode::ReadError))?
+    };
+    response_data[RESPONSE_CODE] = SdoResponse::Read4B as u8;
+    response_data.extend_from_slice(&v.to_be_bytes()).map_err(|_| Error::VectorError)?;
+    new_data_frame(node_id, &response_data)
+}
+
+pub(crate) async fn handle_write_command<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex>(cmd: SdoCmd, data: &[u8], node_id: u8, storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>, map: Option<&RegisterMap<'_>>) -> Result<Frame, Error> {
     let mut response_data = Vec::<u8, 8>::new();
 
     check_header_data(data).map_err(|_| Error::SdoAbort(SdoAbortCode::InvalidQuery))?;
@@ -223,12 +282,12 @@ pub(crate) async fn handle_write_command<'a, const C: usize, const D: usize, con
 
     match cmd {
         SdoCmd::Write2b => {
-            write_u16(data, storage).await.map_err(|e| Error::SdoAbort(e))?;
+            write_u16(data, storage, map).await.map_err(|e| Error::SdoAbort(e))?;
             response_data[RESPONSE_CODE] = SdoResponse::WriteSuccess as u8;
             response_data.extend_from_slice(&data[DATA..DATA+size_of::<u16>()]).map_err(|_| Error::VectorError)?;
         },
         SdoCmd::Write4b => {
-            write_u32(data, storage).await.map_err(|e| Error::SdoAbort(e))?;
+            write_u32(data, storage, map).await.map_err(|e| Error::SdoAbort(e))?;
             response_data[RESPONSE_CODE] = SdoResponse::WriteSuccess as u8;
             response_data.extend_from_slice(&data[DATA..DATA+size_of::<u32>()]).map_err(|_| Error::VectorError)?;
         },
@@ -244,7 +303,7 @@ async fn read_u32<'a, const C: usize, const D: usize, const I: usize, const H: u
         SubIndex::Holding => {
             {
                 let storage = storage.lock().await;
-                let reg = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+                let reg = register(data)?;
                 Ok(storage.get_holdings_as_u32(reg).map_err(|e| {
                     warn!("SdoProcess: read holdings {}", e);
                     SdoAbortCode::ReadError
@@ -254,7 +313,7 @@ async fn read_u32<'a, const C: usize, const D: usize, const I: usize, const H: u
         SubIndex::Input => {
             {
                 let storage = storage.lock().await;
-                let reg = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+                let reg = register(data)?;
                 Ok(storage.get_inputs_as_u32(reg).map_err(|e| {
                     warn!("SdoProcess: read inputs {}", e);
                     SdoAbortCode::ReadError
@@ -275,7 +334,7 @@ async fn read_u16<'a, const C: usize, const D: usize, const I: usize, const H: u
         SubIndex::Holding => {
             {
                 let storage = storage.lock().await;
-                let reg = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+                let reg = register(data)?;
                 Ok(storage.get_holding(reg).map_err(|e| {
                     warn!("SdoProcess: read holdings {}", e);
                     SdoAbortCode::ReadError
@@ -285,7 +344,7 @@ async fn read_u16<'a, const C: usize, const D: usize, const I: usize, const H: u
         SubIndex::Input => {
             {
                 let storage = storage.lock().await;
-                let reg = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+                let reg = register(data)?;
                 Ok(storage.get_input(reg).map_err(|e| {
                     warn!("SdoProcess: read inputs {}", e);
                     SdoAbortCode::ReadError
@@ -323,18 +382,41 @@ pub(crate) async fn create_sdo_abort_response(data: &[u8], node_id: u8, abort_co
 
 
 
-async fn write_u32<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex>(data: &[u8], storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>) -> Result<u32, SdoAbortCode> {
+impl From<Exception> for SdoAbortCode {
+    fn from(value: Exception) -> Self {
+        match value {
+            Exception::IllegalDataAddress => SdoAbortCode::AccessDenied,
+            Exception::IllegalDataValue => SdoAbortCode::InvalidData,
+            Exception::IllegalFunction => SdoAbortCode::NotImplemented,
+            Exception::ServerDeviceFailure => SdoAbortCode::ReadError,
+        }
+    }
+}
+
+/// Check holdings write against the register map, same rules as for Modbus writes
+fn check_holdings_write(map: Option<&RegisterMap<'_>>, reg: u16, values: &[u16]) -> Result<(), SdoAbortCode> {
+    match map {
+        Some(map) => map.check_write(Area::Holding, reg, values.iter().copied()).map_err(|e| SdoAbortCode::from(e)),
+        None => Ok(()),
+    }
+}
+
+async fn write_u32<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex>(data: &[u8], storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>, map: Option<&RegisterMap<'_>>) -> Result<u32, SdoAbortCode> {
     match SubIndex::from(data[SUB_INDEX]) {
         SubIndex::Holding => {
-            let reg = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+            let reg = register(data)?;
             let write_data: [u8; 4] = data[DATA..=DATA_END].try_into().map_err(|_| SdoAbortCode::InvalidData)?;
             let write_value = u32::from_be_bytes(write_data);
+            check_holdings_write(map, reg, &[(write_value >> 16) as u16, write_value as u16])?;
 
             let mut storage = storage.lock().await;
             storage.set_holdings_from_u8(reg, &write_value.to_be_bytes()).map_err(|e| {
                 warn!("SdoProcess: write holdings {}", e);
                 SdoAbortCode::ReadError
             })?;
+            if let Some(map) = map {
+                map.notify_write(Area::Holding, reg, 2);
+            }
             Ok(write_value)
         },
         _ => {
@@ -343,18 +425,22 @@ async fn write_u32<'a, const C: usize, const D: usize, const I: usize, const H:
     }
 }
 
-async fn write_u16<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex>(data: &[u8], storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>) -> Result<u16, SdoAbortCode> {
+async fn write_u16<'a, const C: usize, const D: usize, const I: usize, const H: usize, M: RawMutex>(data: &[u8], storage: &'a Mutex<M, ModbusStorage<C, D, I, H>>, map: Option<&RegisterMap<'_>>) -> Result<u16, SdoAbortCode> {
     match SubIndex::from(data[SUB_INDEX]) {
         SubIndex::Holding => {
-            let reg = u16::from_be_bytes([data[INDEX], data[INDEX_END]]);
+            let reg = register(data)?;
             let write_data: [u8; 2] = data[DATA..=DATA+1].try_into().map_err(|_| SdoAbortCode::InvalidData)?;
             let write_value = u16::from_be_bytes(write_data);
+            check_holdings_write(map, reg, &[write_value])?;
 
             let mut storage = storage.lock().await;
             storage.set_holdings_from_u8(reg, &write_value.to_be_bytes()).map_err(|e| {
                 warn!("SdoProcess: write holdings {}", e);
                 SdoAbortCode::ReadError
             })?;
+            if let Some(map) = map {
+                map.notify_write(Area::Holding, reg, 1);
+            }
             Ok(write_value)
         },
         _ => {
@@ -0,0 +1,185 @@
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use defmt::info;
+use embassy_stm32::can::{Frame, Id};
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{self, Uart};
+use embassy_sync::blocking_mutex::{raw::RawMutex, Mutex};
+use embassy_sync::signal::Signal;
+use embassy_time::Instant;
+use heapless::{Deque, String};
+
+/// Max length of one candump line: `(ssssssssss.uuuuuu) can0 1FFFFFFF#0011223344556677\n`
+pub const CANDUMP_LINE_SIZE: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TraceEntry {
+    pub seq: u32,
+    pub timestamp: Instant,
+    pub direction: Direction,
+    pub id: u32,
+    pub extended: bool,
+    pub len: u8,
+    pub data: [u8; 8],
+}
+
+impl TraceEntry {
+    fn new(seq: u32, direction: Direction, frame: &Frame) -> Self {
+        let (id, extended) = match frame.id() {
+            Id::Standard(id) => (id.as_raw() as u32, false),
+            Id::Extended(id) => (id.as_raw(), true),
+        };
+        let len = frame.data().len().min(8);
+        let mut data = [0u8; 8];
+        data[..len].copy_from_slice(&frame.data()[..len]);
+        Self {
+            seq,
+            timestamp: Instant::now(),
+            direction,
+            id,
+            extended,
+            len: len as u8,
+            data,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    /// Format entry as a candump log line, e.g. `(0000000012.345678) can0 601#4000200200000000`
+    pub fn write_candump<W: Write>(&self, w: &mut W, iface: &str) -> core::fmt::Result {
+        let us = self.timestamp.as_micros();
+        write!(w, "({:010}.{:06}) {} ", us / 1_000_000, us % 1_000_000, iface)?;
+        if self.extended {
+            write!(w, "{:08X}#", self.id)?;
+        } else {
+            write!(w, "{:03X}#", self.id)?;
+        }
+        for b in self.data() {
+            write!(w, "{:02X}", b)?;
+        }
+        w.write_char('\n')
+    }
+
+    /// Entry packed into SDO words: timestamp in ms, id with flags, length, data[0..4], data[4..8]
+    pub fn word(&self, word: u8) -> Option<u32> {
+        match word {
+            0 => Some(self.timestamp.as_millis() as u32),
+            1 => {
+                let dir = match self.direction {
+                    Direction::Rx => 0,
+                    Direction::Tx => 1u32 << 31,
+                };
+                let ext = if self.extended { 1u32 << 29 } else { 0 };
+                Some(dir | ext | (self.id & 0x1FFF_FFFF))
+            }
+            2 => Some(self.len as u32),
+            3 => Some(u32::from_be_bytes([self.data[0], self.data[1], self.data[2], self.data[3]])),
+            4 => Some(u32::from_be_bytes([self.data[4], self.data[5], self.data[6], self.data[7]])),
+            _ => None,
+        }
+    }
+}
+
+pub trait FrameTrace {
+    fn record(&self, direction: Direction, frame: &Frame);
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Entry by position, `0` is the oldest one still in the buffer
+    fn entry(&self, index: usize) -> Option<TraceEntry>;
+}
+
+struct Inner<const N: usize> {
+    seq: u32,
+    entries: Deque<TraceEntry, N>,
+}
+
+/// Ring buffer of the last `N` frames seen or sent by `CanServer`.
+pub struct CanTrace<M: RawMutex, const N: usize> {
+    inner: Mutex<M, RefCell<Inner<N>>>,
+    signal: Signal<M, ()>,
+}
+
+impl<M: RawMutex, const N: usize> CanTrace<M, N> {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner { seq: 0, entries: Deque::new() })),
+            signal: Signal::new(),
+        }
+    }
+
+    pub fn clear(&self) {
+        self.inner.lock(|inner| inner.borrow_mut().entries.clear());
+    }
+
+    /// Print the whole buffer over defmt
+    pub fn dump(&self) {
+        self.inner.lock(|inner| {
+            for e in inner.borrow().entries.iter() {
+                info!("CanTrace: #{} {} {}ms {:x} {}", e.seq, e.direction, e.timestamp.as_millis(), e.id, e.data());
+            }
+        });
+    }
+
+    fn next_after(&self, seq: Option<u32>) -> Option<TraceEntry> {
+        self.inner.lock(|inner| {
+            let inner = inner.borrow();
+            inner.entries.iter().find(|e| seq.map_or(true, |s| e.seq.wrapping_sub(s) as i32 > 0)).copied()
+        })
+    }
+
+    /// Stream every recorded frame out of `uart` in candump text format. Never returns on success.
+    pub async fn stream(&self, uart: &mut Uart<'_, Async>, iface: &str) -> Result<(), usart::Error> {
+        let mut last: Option<u32> = None;
+        loop {
+            while let Some(entry) = self.next_after(last) {
+                last = Some(entry.seq);
+                let mut line: String<CANDUMP_LINE_SIZE> = String::new();
+                if entry.write_candump(&mut line, iface).is_ok() {
+                    uart.write(line.as_bytes()).await?;
+                }
+            }
+            self.signal.wait().await;
+        }
+    }
+}
+
+impl<M: RawMutex, const N: usize> Default for CanTrace<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize> FrameTrace for CanTrace<M, N> {
+    fn record(&self, direction: Direction, frame: &Frame) {
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            let entry = TraceEntry::new(inner.seq, direction, frame);
+            inner.seq = inner.seq.wrapping_add(1);
+            if inner.entries.is_full() {
+                inner.entries.pop_front();
+            }
+            let _ = inner.entries.push_back(entry);
+        });
+        self.signal.signal(());
+    }
+
+    fn len(&self) -> usize {
+        self.inner.lock(|inner| inner.borrow().entries.len())
+    }
+
+    fn entry(&self, index: usize) -> Option<TraceEntry> {
+        self.inner.lock(|inner| inner.borrow().entries.iter().nth(index).copied())
+    }
+}
@@ -0,0 +1,59 @@
+use embassy_stm32::usart;
+use embassy_time::Duration;
+use rtu::RtuTiming;
+
+use crate::components::com::transport::Transport;
+
+pub mod rtu;
+pub mod ascii;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameError {
+    Uart(usart::Error),
+    /// No answer within the response timeout
+    Timeout,
+    /// Silence longer than t1.5 inside an RTU frame or bad characters in an ASCII frame
+    Broken,
+    /// Frame does not fit into the receive buffer
+    Overflow,
+    /// CRC or LRC mismatch
+    Crc,
+}
+
+/// Serial line encoding. Frames passed in and out are always RTU ADUs (address, PDU, CRC),
+/// ASCII frames are converted at the link, so rmodbus is always driven with `ModbusProto::Rtu`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModbusMode {
+    Rtu(RtuTiming),
+    Ascii,
+}
+
+impl ModbusMode {
+    pub const fn rtu(baudrate: u32) -> Self {
+        ModbusMode::Rtu(RtuTiming::from_baudrate(baudrate))
+    }
+
+    /// Wait for a request
+    pub async fn read_frame<T: Transport>(&self, port: &mut T, buf: &mut [u8]) -> Result<usize, FrameError> {
+        match self {
+            ModbusMode::Rtu(timing) => rtu::read_frame(port, timing, buf).await,
+            ModbusMode::Ascii => ascii::read_frame(port, buf, None).await,
+        }
+    }
+
+    /// Wait for an answer that has to start within `timeout`
+    pub async fn read_response<T: Transport>(&self, port: &mut T, buf: &mut [u8], timeout: Duration) -> Result<usize, FrameError> {
+        match self {
+            ModbusMode::Rtu(timing) => rtu::read_response(port, timing, buf, timeout).await,
+            ModbusMode::Ascii => ascii::read_response(port, buf, timeout).await,
+        }
+    }
+
+    pub async fn write_frame<T: Transport>(&self, port: &mut T, adu: &[u8]) -> Result<(), FrameError> {
+        match self {
+            ModbusMode::Rtu(_) => port.write(adu).await.map_err(|e| FrameError::Uart(e)),
+            ModbusMode::Ascii => ascii::write_frame(port, adu).await,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+use crate::components::com::transport::Transport;
+use super::{rtu, FrameError};
+
+/// ':' + 2 hex chars for each of 256 bytes + LRC + CR/LF
+pub const MAX_ASCII_FRAME_SIZE: usize = 1 + 2 * 256 + 2 + 2;
+
+const START: u8 = b':';
+const CR: u8 = b'\r';
+const LF: u8 = b'\n';
+/// Default max silence between the characters of a frame
+const INTER_CHAR_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn lrc(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)).wrapping_neg()
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
+fn hex_char(v: u8) -> u8 {
+    b"0123456789ABCDEF"[(v & 0x0F) as usize]
+}
+
+/// Decode hex payload between ':' and CR/LF into an RTU ADU (address, PDU, CRC)
+fn decode(payload: &[u8], buf: &mut [u8]) -> Result<usize, FrameError> {
+    if payload.len() % 2 != 0 || payload.len() < 6 {
+        return Err(FrameError::Broken);
+    }
+    let len = payload.len() / 2;
+    // LRC is replaced by the two CRC bytes
+    if len + 1 > buf.len() {
+        return Err(FrameError::Overflow);
+    }
+    for (i, pair) in payload.chunks_exact(2).enumerate() {
+        let hi = hex_value(pair[0]).ok_or(FrameError::Broken)?;
+        let lo = hex_value(pair[1]).ok_or(FrameError::Broken)?;
+        buf[i] = hi << 4 | lo;
+    }
+    let data_len = len - 1;
+    if lrc(&buf[..data_len]) != buf[data_len] {
+        return Err(FrameError::Crc);
+    }
+    let crc = rtu::crc16(&buf[..data_len]).to_le_bytes();
+    buf[data_len..data_len + 2].copy_from_slice(&crc);
+    Ok(data_len + 2)
+}
+
+/// Encode an RTU ADU (CRC is dropped) as an ASCII frame
+pub fn encode(adu: &[u8], out: &mut Vec<u8, MAX_ASCII_FRAME_SIZE>) -> Result<(), FrameError> {
+    if adu.len() < rtu::MIN_FRAME_SIZE {
+        return Err(FrameError::Broken);
+    }
+    let data = &adu[..adu.len() - 2];
+    out.clear();
+    out.push(START).map_err(|_| FrameError::Overflow)?;
+    for b in data.iter().chain(core::iter::once(&lrc(data))) {
+        out.push(hex_char(b >> 4)).map_err(|_| FrameError::Overflow)?;
+        out.push(hex_char(*b)).map_err(|_| FrameError::Overflow)?;
+    }
+    out.extend_from_slice(&[CR, LF]).map_err(|_| FrameError::Overflow)
+}
+
+/// Read one ':' .. CR/LF frame. Characters are read one at a time, so nothing after the
+/// LF is consumed and a back to back frame stays in the port for the next call. Anything
+/// before ':' is dropped and a second ':' restarts the frame. `deadline` only applies to the
+/// leading ':', later characters have to follow within `INTER_CHAR_TIMEOUT`.
+/// Returns the frame converted to an RTU ADU.
+pub async fn read_frame<T: Transport>(port: &mut T, buf: &mut [u8], deadline: Option<Instant>) -> Result<usize, FrameError> {
+    let mut line: Vec<u8, MAX_ASCII_FRAME_SIZE> = Vec::new();
+    let mut started = false;
+    let mut byte = [0u8; 1];
+    loop {
+        let limit = if started { Some(Instant::now() + INTER_CHAR_TIMEOUT) } else { deadline };
+        match limit {
+            Some(limit) => match select(port.read(&mut byte), Timer::at(limit)).await {
+                Either::First(res) => res.map_err(|e| FrameError::Uart(e))?,
+                Either::Second(_) if started => return Err(FrameError::Broken),
+                Either::Second(_) => return Err(FrameError::Timeout),
+            },
+            None => port.read(&mut byte).await.map_err(|e| FrameError::Uart(e))?,
+        }
+        let c = byte[0];
+        if c == START {
+            started = true;
+            line.clear();
+            continue;
+        }
+        if !started {
+            continue;
+        }
+        if c == LF && line.last() == Some(&CR) {
+            return decode(&line[..line.len() - 1], buf);
+        }
+        if line.push(c).is_err() {
+            started = false;
+            line.clear();
+        }
+    }
+}
+
+/// Wait for an answer, the leading ':' must arrive within `timeout`
+pub async fn read_response<T: Transport>(port: &mut T, buf: &mut [u8], timeout: Duration) -> Result<usize, FrameError> {
+    read_frame(port, buf, Some(Instant::now() + timeout)).await
+}
+
+pub async fn write_frame<T: Transport>(port: &mut T, adu: &[u8]) -> Result<(), FrameError> {
+    let mut out: Vec<u8, MAX_ASCII_FRAME_SIZE> = Vec::new();
+    encode(adu, &mut out)?;
+    port.write(&out).await.map_err(|e| FrameError::Uart(e))
+}
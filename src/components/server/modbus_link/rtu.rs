@@ -0,0 +1,115 @@
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::components::com::transport::Transport;
+pub use crate::components::crc::crc16;
+use super::FrameError;
+
+/// Shortest valid RTU frame: address, function and CRC
+pub const MIN_FRAME_SIZE: usize = 4;
+
+/// Modbus RTU character timings for a given baud rate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RtuTiming {
+    /// Time to transmit one 11 bit character
+    pub char_time: Duration,
+    /// Max silence between two characters of a frame
+    pub t15: Duration,
+    /// Min silence between two frames
+    pub t35: Duration,
+}
+
+impl RtuTiming {
+    pub const fn from_baudrate(baudrate: u32) -> Self {
+        let char_us = 11 * 1_000_000 / baudrate as u64;
+        if baudrate > 19200 {
+            // fixed values recommended by the spec for high baud rates
+            Self {
+                char_time: Duration::from_micros(char_us),
+                t15: Duration::from_micros(750),
+                t35: Duration::from_micros(1750),
+            }
+        } else {
+            Self {
+                char_time: Duration::from_micros(char_us),
+                t15: Duration::from_micros(char_us * 3 / 2),
+                t35: Duration::from_micros(char_us * 7 / 2),
+            }
+        }
+    }
+}
+
+/// Check the CRC of a full frame, CRC is sent low byte first
+pub fn check_crc(frame: &[u8]) -> bool {
+    if frame.len() < MIN_FRAME_SIZE {
+        return false;
+    }
+    let (data, crc) = frame.split_at(frame.len() - 2);
+    crc16(data).to_le_bytes() == [crc[0], crc[1]]
+}
+
+/// Wait for a frame, returns its length with CRC. Broken frames are read up to the
+/// next t3.5 silence and then dropped.
+pub async fn read_frame<T: Transport>(port: &mut T, timing: &RtuTiming, buf: &mut [u8]) -> Result<usize, FrameError> {
+    let mut byte = [0u8; 1];
+    port.read(&mut byte).await.map_err(|e| FrameError::Uart(e))?;
+    read_rest(port, timing, buf, byte[0]).await
+}
+
+/// Wait for an answer, the first character must arrive within `timeout`
+pub async fn read_response<T: Transport>(port: &mut T, timing: &RtuTiming, buf: &mut [u8], timeout: Duration) -> Result<usize, FrameError> {
+    let mut byte = [0u8; 1];
+    match select(port.read(&mut byte), Timer::after(timeout)).await {
+        Either::First(res) => res.map_err(|e| FrameError::Uart(e))?,
+        Either::Second(_) => return Err(FrameError::Timeout),
+    }
+    read_rest(port, timing, buf, byte[0]).await
+}
+
+async fn read_rest<T: Transport>(port: &mut T, timing: &RtuTiming, buf: &mut [u8], first: u8) -> Result<usize, FrameError> {
+    let mut byte = [0u8; 1];
+    let mut len = 0;
+    let mut error = None;
+    let mut last = Instant::now();
+    if let Some(b) = buf.first_mut() {
+        *b = first;
+        len = 1;
+    } else {
+        error = Some(FrameError::Overflow);
+    }
+
+    // gaps are measured between the ends of two characters, so one character time is added
+    let max_gap = timing.char_time + timing.t15;
+    loop {
+        match select(port.read(&mut byte), Timer::at(last + timing.char_time + timing.t35)).await {
+            Either::First(Ok(())) => {
+                let now = Instant::now();
+                if now - last > max_gap && error.is_none() {
+                    error = Some(FrameError::Broken);
+                }
+                last = now;
+                if len < buf.len() {
+                    buf[len] = byte[0];
+                    len += 1;
+                } else if error.is_none() {
+                    error = Some(FrameError::Overflow);
+                }
+            },
+            Either::First(Err(e)) => {
+                if error.is_none() {
+                    error = Some(FrameError::Uart(e));
+                }
+                last = Instant::now();
+            },
+            Either::Second(_) => break,
+        }
+    }
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+    if !check_crc(&buf[..len]) {
+        return Err(FrameError::Crc);
+    }
+    Ok(len)
+}
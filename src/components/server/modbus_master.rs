@@ -1,4 +1,9 @@
+pub mod bus;
+pub mod client;
 pub mod encoder;
+pub mod health;
+pub mod poller;
+pub mod scan;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -0,0 +1,289 @@
+use core::cell::RefCell;
+use core::cmp::Reverse;
+use core::future::poll_fn;
+use core::ops::{Deref, DerefMut};
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::{raw::RawMutex, Mutex as BlockingMutex};
+use embassy_sync::mutex::{Mutex, MutexGuard};
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::{Duration, Instant, Timer};
+use heapless::Vec;
+
+use crate::components::com::transport::Transport;
+use crate::components::server::modbus_link::rtu::RtuTiming;
+
+const MAX_WAITERS: usize = 8;
+const DEFAULT_AGING: Duration = Duration::from_secs(1);
+
+/// Lower value goes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Priority {
+    /// E.g. setpoint writes
+    Urgent = 0,
+    Normal = 1,
+    /// Cyclic polling
+    Background = 2,
+}
+
+impl Priority {
+    /// One level higher, `Urgent` stays
+    fn raised(self) -> Self {
+        match self {
+            Priority::Urgent | Priority::Normal => Priority::Urgent,
+            Priority::Background => Priority::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Request {
+    ticket: u32,
+    priority: Priority,
+    session: Option<u32>,
+    queued_at: Instant,
+}
+
+struct State {
+    busy: bool,
+    /// Only transactions of this session are granted while set
+    exclusive: Option<u32>,
+    next_session: u32,
+    next_ticket: u32,
+    queue: Vec<Request, MAX_WAITERS>,
+    /// Waiting time after which a queued request is raised by one priority level
+    aging: Duration,
+    /// Earliest start of the next transaction
+    ready_at: Instant,
+    frame_gap: Duration,
+    turnaround: Duration,
+    wakers: MultiWakerRegistration<MAX_WAITERS>,
+}
+
+/// Master side owner of a shared serial line. Transactions are granted by priority,
+/// separated by the inter-frame gap, and broadcasts are followed by the turnaround delay.
+pub struct ModbusBus<M: RawMutex, T: Transport> {
+    port: Mutex<M, T>,
+    state: BlockingMutex<M, RefCell<State>>,
+}
+
+impl<M: RawMutex, T: Transport> ModbusBus<M, T> {
+    pub fn new(port: T, timing: RtuTiming) -> Self {
+        Self::new_advanced(port, timing.char_time + timing.t35, Duration::from_millis(100))
+    }
+
+    /// `frame_gap` is the silence kept between two transactions, `turnaround` the delay after a broadcast
+    pub fn new_advanced(port: T, frame_gap: Duration, turnaround: Duration) -> Self {
+        Self {
+            port: Mutex::new(port),
+            state: BlockingMutex::new(RefCell::new(State {
+                busy: false,
+                exclusive: None,
+                next_session: 0,
+                next_ticket: 0,
+                queue: Vec::new(),
+                aging: DEFAULT_AGING,
+                ready_at: Instant::from_ticks(0),
+                frame_gap,
+                turnaround,
+                wakers: MultiWakerRegistration::new(),
+            })),
+        }
+    }
+
+    /// E.g. after the baud rate was changed
+    pub fn set_frame_gap(&self, frame_gap: Duration) {
+        self.state.lock(|s| s.borrow_mut().frame_gap = frame_gap);
+    }
+
+    pub fn set_turnaround(&self, turnaround: Duration) {
+        self.state.lock(|s| s.borrow_mut().turnaround = turnaround);
+    }
+
+    /// A queued request is raised by one priority level for every `aging` it waited,
+    /// so constant urgent traffic can not starve background polling
+    pub fn set_aging(&self, aging: Duration) {
+        self.state.lock(|s| s.borrow_mut().aging = aging);
+    }
+
+    /// Wait until the request is the first in line, the line is free and the gap has passed.
+    /// Requests are served by priority and in order of arrival within the same priority.
+    pub async fn acquire(&self, priority: Priority) -> BusGuard<'_, M, T> {
+        self.acquire_in(priority, None).await
+    }
+
+    /// Reserve the line for a series of transactions, e.g. a scan at other serial settings.
+    /// Until the session is dropped only clients created `with_session` get the line,
+    /// one queue slot is kept free for them.
+    pub async fn exclusive(&self) -> ExclusiveSession<'_, M, T> {
+        let guard = self.acquire(Priority::Urgent).await;
+        let id = self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            let id = s.next_session;
+            s.next_session = id.wrapping_add(1);
+            s.exclusive = Some(id);
+            id
+        });
+        drop(guard);
+        ExclusiveSession { bus: self, id }
+    }
+
+    /// `acquire` for the transactions of `session`, if any
+    pub(crate) async fn acquire_in(&self, priority: Priority, session: Option<u32>) -> BusGuard<'_, M, T> {
+        let mut waiting = Waiting { bus: self, ticket: None };
+        poll_fn(|cx| {
+            self.state.lock(|s| {
+                let mut s = s.borrow_mut();
+                let ticket = match waiting.ticket {
+                    Some(ticket) => ticket,
+                    None => {
+                        let ticket = s.next_ticket;
+                        let request = Request { ticket, priority, session, queued_at: Instant::now() };
+                        // the last slot is kept for the active session, waiters outside it can not lock it out
+                        let limit = if session.is_some() && session == s.exclusive { MAX_WAITERS } else { MAX_WAITERS - 1 };
+                        if s.queue.len() >= limit || s.queue.push(request).is_err() {
+                            // queue full, retry when a request leaves it
+                            s.wakers.register(cx.waker());
+                            return Poll::Pending;
+                        }
+                        s.next_ticket = ticket.wrapping_add(1);
+                        waiting.ticket = Some(ticket);
+                        ticket
+                    },
+                };
+                if !s.busy && s.next() == Some(ticket) {
+                    s.busy = true;
+                    s.remove(ticket);
+                    Poll::Ready(())
+                } else {
+                    s.wakers.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+        .await;
+        waiting.ticket = None;
+
+        let grant = Grant { bus: self, broadcast: false };
+        let ready_at = self.state.lock(|s| s.borrow().ready_at);
+        Timer::at(ready_at).await;
+        BusGuard { port: self.port.lock().await, grant }
+    }
+
+    fn release(&self, broadcast: bool) {
+        self.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.busy = false;
+            s.ready_at = Instant::now() + if broadcast { s.turnaround } else { s.frame_gap };
+            s.wakers.wake();
+        });
+    }
+}
+
+impl State {
+    /// Ticket of the request to grant next: highest priority after aging, then the oldest.
+    /// While a session is active only its requests are considered.
+    fn next(&self) -> Option<u32> {
+        let now = Instant::now();
+        let aging = self.aging.as_ticks().max(1);
+        self.queue
+            .iter()
+            .filter(|r| self.exclusive.is_none() || r.session == self.exclusive)
+            .min_by_key(|r| {
+                let mut priority = r.priority;
+                let steps = now.saturating_duration_since(r.queued_at).as_ticks() / aging;
+                for _ in 0..steps.min(2) {
+                    priority = priority.raised();
+                }
+                // tickets wrap, compare by age relative to the next one
+                (priority, Reverse(self.next_ticket.wrapping_sub(r.ticket)))
+            })
+            .map(|r| r.ticket)
+    }
+
+    fn remove(&mut self, ticket: u32) {
+        if let Some(i) = self.queue.iter().position(|r| r.ticket == ticket) {
+            self.queue.swap_remove(i);
+        }
+    }
+}
+
+/// Reservation of the line returned by `ModbusBus::exclusive`, other transactions wait until it is dropped
+pub struct ExclusiveSession<'a, M: RawMutex, T: Transport> {
+    bus: &'a ModbusBus<M, T>,
+    id: u32,
+}
+
+impl<M: RawMutex, T: Transport> ExclusiveSession<'_, M, T> {
+    pub(crate) fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl<M: RawMutex, T: Transport> Drop for ExclusiveSession<'_, M, T> {
+    fn drop(&mut self) {
+        self.bus.state.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.exclusive = None;
+            s.wakers.wake();
+        });
+    }
+}
+
+/// Queued `acquire`, leaves the queue when the future is dropped
+struct Waiting<'a, M: RawMutex, T: Transport> {
+    bus: &'a ModbusBus<M, T>,
+    ticket: Option<u32>,
+}
+
+impl<M: RawMutex, T: Transport> Drop for Waiting<'_, M, T> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            self.bus.state.lock(|s| {
+                let mut s = s.borrow_mut();
+                s.remove(ticket);
+                s.wakers.wake();
+            });
+        }
+    }
+}
+
+struct Grant<'a, M: RawMutex, T: Transport> {
+    bus: &'a ModbusBus<M, T>,
+    broadcast: bool,
+}
+
+impl<M: RawMutex, T: Transport> Drop for Grant<'_, M, T> {
+    fn drop(&mut self) {
+        self.bus.release(self.broadcast);
+    }
+}
+
+/// Exclusive access to the line for one transaction, the gap starts when it is dropped
+pub struct BusGuard<'a, M: RawMutex, T: Transport> {
+    // dropped before `grant`, so the port is unlocked when the next transaction is granted
+    port: MutexGuard<'a, M, T>,
+    grant: Grant<'a, M, T>,
+}
+
+impl<M: RawMutex, T: Transport> BusGuard<'_, M, T> {
+    /// Keep the turnaround delay instead of the frame gap after this transaction
+    pub fn broadcast_sent(&mut self) {
+        self.grant.broadcast = true;
+    }
+}
+
+impl<M: RawMutex, T: Transport> Deref for BusGuard<'_, M, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.port
+    }
+}
+
+impl<M: RawMutex, T: Transport> DerefMut for BusGuard<'_, M, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.port
+    }
+}
@@ -0,0 +1,348 @@
+use defmt::{trace, warn};
+use embassy_stm32::usart;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Duration;
+use heapless::Vec;
+
+use crate::components::com::transport::Transport;
+use crate::components::server::modbus_link::{rtu, FrameError, ModbusMode};
+use super::bus::{ExclusiveSession, ModbusBus, Priority};
+
+pub const BROADCAST_ID: u8 = 0;
+const MODBUS_BUF_SIZE: usize = 256;
+const MAX_READ_BITS: usize = 2000;
+const MAX_READ_REGISTERS: usize = 125;
+const MAX_WRITE_BITS: usize = 1968;
+const MAX_WRITE_REGISTERS: usize = 123;
+/// FC 23 can write less registers than FC 16
+const MAX_READ_WRITE_REGISTERS: usize = 121;
+
+/// Exception codes answered by a slave
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExceptionCode {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    Acknowledge,
+    ServerDeviceBusy,
+    MemoryParityError,
+    GatewayPathUnavailable,
+    GatewayTargetFailed,
+    Unknown(u8),
+}
+
+impl From<u8> for ExceptionCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => ExceptionCode::IllegalFunction,
+            0x02 => ExceptionCode::IllegalDataAddress,
+            0x03 => ExceptionCode::IllegalDataValue,
+            0x04 => ExceptionCode::ServerDeviceFailure,
+            0x05 => ExceptionCode::Acknowledge,
+            0x06 => ExceptionCode::ServerDeviceBusy,
+            0x08 => ExceptionCode::MemoryParityError,
+            0x0A => ExceptionCode::GatewayPathUnavailable,
+            0x0B => ExceptionCode::GatewayTargetFailed,
+            v => ExceptionCode::Unknown(v),
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// No answer within the timeout, after all retries
+    Timeout,
+    Uart(usart::Error),
+    Frame(FrameError),
+    Exception(ExceptionCode),
+    /// Answer from another slave, for another function or with a wrong length
+    UnexpectedResponse,
+    /// Quantity out of the range allowed by the function or request too long
+    InvalidRequest,
+}
+
+impl From<FrameError> for Error {
+    fn from(value: FrameError) -> Self {
+        match value {
+            FrameError::Timeout => Error::Timeout,
+            FrameError::Uart(e) => Error::Uart(e),
+            e => Error::Frame(e),
+        }
+    }
+}
+
+impl Error {
+    /// Errors worth sending the request again for
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Error::Timeout | Error::Frame(_) | Error::Uart(_) | Error::UnexpectedResponse)
+    }
+}
+
+type Pdu = Vec<u8, MODBUS_BUF_SIZE>;
+
+fn push(pdu: &mut Pdu, data: &[u8]) -> Result<(), Error> {
+    pdu.extend_from_slice(data).map_err(|_| Error::InvalidRequest)
+}
+
+fn request(func: u8, start: u16, value: u16) -> Result<Pdu, Error> {
+    let mut pdu = Pdu::new();
+    push(&mut pdu, &[func])?;
+    push(&mut pdu, &start.to_be_bytes())?;
+    push(&mut pdu, &value.to_be_bytes())?;
+    Ok(pdu)
+}
+
+fn push_registers(pdu: &mut Pdu, values: &[u16]) -> Result<(), Error> {
+    push(pdu, &[(values.len() * 2) as u8])?;
+    for value in values {
+        push(pdu, &value.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+fn check_count(count: usize, max: usize) -> Result<u16, Error> {
+    if count == 0 || count > max {
+        return Err(Error::InvalidRequest);
+    }
+    Ok(count as u16)
+}
+
+/// Data of a read answer after checking its byte count
+fn read_data(response: &[u8], bytes: usize) -> Result<&[u8], Error> {
+    if response.len() != bytes + 2 || response[1] as usize != bytes {
+        return Err(Error::UnexpectedResponse);
+    }
+    Ok(&response[2..])
+}
+
+fn decode_bits(data: &[u8], values: &mut [bool]) {
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = data[i / 8] >> (i % 8) & 1 == 1;
+    }
+}
+
+fn decode_registers(data: &[u8], values: &mut [u16]) {
+    for (value, bytes) in values.iter_mut().zip(data.chunks_exact(2)) {
+        *value = u16::from_be_bytes([bytes[0], bytes[1]]);
+    }
+}
+
+/// Modbus RTU/ASCII master on a shared `ModbusBus`. Every request is retried on timeouts and
+/// broken answers, exceptions are returned right away.
+pub struct ModbusClient<'a, M: RawMutex, T: Transport> {
+    bus: &'a ModbusBus<M, T>,
+    mode: ModbusMode,
+    timeout: Duration,
+    retries: u8,
+    priority: Priority,
+    session: Option<u32>,
+}
+
+impl<'a, M: RawMutex, T: Transport> ModbusClient<'a, M, T> {
+    pub fn new(bus: &'a ModbusBus<M, T>, mode: ModbusMode) -> Self {
+        Self::new_advanced(bus, mode, Duration::from_millis(100), 2)
+    }
+
+    pub fn new_advanced(bus: &'a ModbusBus<M, T>, mode: ModbusMode, timeout: Duration, retries: u8) -> Self {
+        Self { bus, mode, timeout, retries, priority: Priority::Normal, session: None }
+    }
+
+    /// Same client with another timeout, e.g. for a single slow request
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self { timeout, ..*self }
+    }
+
+    pub fn with_retries(&self, retries: u8) -> Self {
+        Self { retries, ..*self }
+    }
+
+    /// Same client with another bus priority, e.g. `Priority::Urgent` for setpoint writes
+    pub fn with_priority(&self, priority: Priority) -> Self {
+        Self { priority, ..*self }
+    }
+
+    /// Same client running its transactions inside `session`
+    pub fn with_session(&self, session: &ExclusiveSession<'_, M, T>) -> Self {
+        Self { session: Some(session.id()), ..*self }
+    }
+
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_retries(&mut self, retries: u8) {
+        self.retries = retries;
+    }
+
+    pub fn mode(&self) -> ModbusMode {
+        self.mode
+    }
+
+    /// Send a request PDU to `unit_id` and return the answer PDU. Broadcasts return an empty PDU.
+    pub async fn transaction(&self, unit_id: u8, pdu: &[u8]) -> Result<Pdu, Error> {
+        let mut adu: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
+        adu.push(unit_id).map_err(|_| Error::InvalidRequest)?;
+        adu.extend_from_slice(pdu).map_err(|_| Error::InvalidRequest)?;
+        adu.extend_from_slice(&rtu::crc16(&adu).to_le_bytes()).map_err(|_| Error::InvalidRequest)?;
+
+        let mut attempt = 0;
+        loop {
+            match self.exchange(&adu).await {
+                Err(e) if e.is_transient() && attempt < self.retries => {
+                    attempt += 1;
+                    warn!("ModbusClient: {} to {}, retry {}", e, unit_id, attempt);
+                },
+                res => return res,
+            }
+        }
+    }
+
+    async fn exchange(&self, adu: &[u8]) -> Result<Pdu, Error> {
+        let mut buf = [0u8; MODBUS_BUF_SIZE];
+        let count = {
+            let mut bus = self.bus.acquire_in(self.priority, self.session).await;
+            trace!("ModbusClient: TX {}", adu);
+            self.mode.write_frame(&mut *bus, adu).await?;
+            if adu[0] == BROADCAST_ID {
+                bus.broadcast_sent();
+                return Ok(Pdu::new());
+            }
+            self.mode.read_response(&mut *bus, &mut buf, self.timeout).await?
+        };
+        trace!("ModbusClient: RX {}", &buf[..count]);
+        // address, function and CRC at least
+        if count < rtu::MIN_FRAME_SIZE || buf[0] != adu[0] {
+            return Err(Error::UnexpectedResponse);
+        }
+        let func = adu[1];
+        let pdu = &buf[1..count - 2];
+        if pdu[0] == func | 0x80 {
+            return Err(Error::Exception(pdu.get(1).copied().unwrap_or(0).into()));
+        }
+        if pdu[0] != func {
+            return Err(Error::UnexpectedResponse);
+        }
+        Pdu::from_slice(pdu).map_err(|_| Error::UnexpectedResponse)
+    }
+
+    async fn read_bits(&self, func: u8, unit_id: u8, start: u16, values: &mut [bool]) -> Result<(), Error> {
+        let count = check_count(values.len(), MAX_READ_BITS)?;
+        let response = self.transaction(unit_id, &request(func, start, count)?).await?;
+        decode_bits(read_data(&response, values.len().div_ceil(8))?, values);
+        Ok(())
+    }
+
+    async fn read_registers(&self, func: u8, unit_id: u8, start: u16, values: &mut [u16]) -> Result<(), Error> {
+        let count = check_count(values.len(), MAX_READ_REGISTERS)?;
+        let response = self.transaction(unit_id, &request(func, start, count)?).await?;
+        decode_registers(read_data(&response, values.len() * 2)?, values);
+        Ok(())
+    }
+
+    /// Writes are answered with the first 5 bytes of the request
+    async fn write(&self, unit_id: u8, pdu: &[u8]) -> Result<(), Error> {
+        let response = self.transaction(unit_id, pdu).await?;
+        if unit_id != BROADCAST_ID && response.as_slice() != &pdu[..5] {
+            return Err(Error::UnexpectedResponse);
+        }
+        Ok(())
+    }
+
+    /// FC 01, fills `values` starting at `start`
+    pub async fn read_coils(&self, unit_id: u8, start: u16, values: &mut [bool]) -> Result<(), Error> {
+        self.read_bits(0x01, unit_id, start, values).await
+    }
+
+    /// FC 02
+    pub async fn read_discretes(&self, unit_id: u8, start: u16, values: &mut [bool]) -> Result<(), Error> {
+        self.read_bits(0x02, unit_id, start, values).await
+    }
+
+    /// FC 03
+    pub async fn read_holdings(&self, unit_id: u8, start: u16, values: &mut [u16]) -> Result<(), Error> {
+        self.read_registers(0x03, unit_id, start, values).await
+    }
+
+    /// FC 04
+    pub async fn read_inputs(&self, unit_id: u8, start: u16, values: &mut [u16]) -> Result<(), Error> {
+        self.read_registers(0x04, unit_id, start, values).await
+    }
+
+    /// FC 05
+    pub async fn write_coil(&self, unit_id: u8, reg: u16, value: bool) -> Result<(), Error> {
+        self.write(unit_id, &request(0x05, reg, if value { 0xFF00 } else { 0x0000 })?).await
+    }
+
+    /// FC 06
+    pub async fn write_holding(&self, unit_id: u8, reg: u16, value: u16) -> Result<(), Error> {
+        self.write(unit_id, &request(0x06, reg, value)?).await
+    }
+
+    /// FC 15
+    pub async fn write_coils(&self, unit_id: u8, start: u16, values: &[bool]) -> Result<(), Error> {
+        let count = check_count(values.len(), MAX_WRITE_BITS)?;
+        let mut pdu = request(0x0F, start, count)?;
+        push(&mut pdu, &[values.len().div_ceil(8) as u8])?;
+        for bits in values.chunks(8) {
+            let byte = bits.iter().enumerate().fold(0u8, |byte, (i, &bit)| byte | (bit as u8) << i);
+            push(&mut pdu, &[byte])?;
+        }
+        self.write(unit_id, &pdu).await
+    }
+
+    /// FC 16
+    pub async fn write_holdings(&self, unit_id: u8, start: u16, values: &[u16]) -> Result<(), Error> {
+        let count = check_count(values.len(), MAX_WRITE_REGISTERS)?;
+        let mut pdu = request(0x10, start, count)?;
+        push_registers(&mut pdu, values)?;
+        self.write(unit_id, &pdu).await
+    }
+
+    /// FC 22, `value = (value & and_mask) | (or_mask & !and_mask)`
+    pub async fn mask_write_holding(&self, unit_id: u8, reg: u16, and_mask: u16, or_mask: u16) -> Result<(), Error> {
+        let mut pdu = request(0x16, reg, and_mask)?;
+        push(&mut pdu, &or_mask.to_be_bytes())?;
+        let response = self.transaction(unit_id, &pdu).await?;
+        if unit_id != BROADCAST_ID && response != pdu {
+            return Err(Error::UnexpectedResponse);
+        }
+        Ok(())
+    }
+
+    /// FC 43 / MEI 14 read device identification, returns the answer PDU
+    pub async fn read_device_identification(&self, unit_id: u8, read_code: u8, object_id: u8) -> Result<Pdu, Error> {
+        let response = self.transaction(unit_id, &[0x2B, 0x0E, read_code, object_id]).await?;
+        // function, MEI type, read code, conformity level, more follows, next object id, number of objects
+        if response.len() < 7 || response[1] != 0x0E {
+            return Err(Error::UnexpectedResponse);
+        }
+        Ok(response)
+    }
+
+    /// FC 23, the write is executed before the read
+    pub async fn read_write_holdings(&self, unit_id: u8, read_start: u16, read: &mut [u16], write_start: u16, write: &[u16]) -> Result<(), Error> {
+        if unit_id == BROADCAST_ID {
+            return Err(Error::InvalidRequest);
+        }
+        let read_count = check_count(read.len(), MAX_READ_REGISTERS)?;
+        let write_count = check_count(write.len(), MAX_READ_WRITE_REGISTERS)?;
+        let mut pdu = request(0x17, read_start, read_count)?;
+        push(&mut pdu, &write_start.to_be_bytes())?;
+        push(&mut pdu, &write_count.to_be_bytes())?;
+        push_registers(&mut pdu, write)?;
+        let response = self.transaction(unit_id, &pdu).await?;
+        decode_registers(read_data(&response, read.len() * 2)?, read);
+        Ok(())
+    }
+}
@@ -1,12 +1,13 @@
-use defmt::info;
-use embassy_futures::select::{self};
-use embassy_stm32::usart::{self};
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
-use embassy_time::{Duration, Timer};
-use heapless::Vec;
-use rmodbus::{client::ModbusRequest, ModbusProto};
+use defmt::{trace, warn};
+use embassy_sync::{blocking_mutex::raw::RawMutex, watch::Watch};
+use embassy_time::{Duration, Instant, Ticker};
 
-use crate::components::com::rs485::Rs485;
+use crate::components::com::transport::Transport;
+use super::bus::Priority;
+use super::client::{self, ModbusClient};
+
+pub mod motion;
+use motion::{MotionConfig, MotionTracker};
 
 mod regs {
     #![allow(unused)] 
@@ -21,125 +22,139 @@ mod regs {
     pub const NODE_ID:          u16 = 250;
 }
 
+/// `ROTATION_ANGLE_F` unit in degrees
+const ANGLE_FRACTION_SCALE: f32 = 0.01;
+const MAX_NODE_ID: u16 = 247;
+const DEGREES_PER_TURN: u16 = 360;
+/// Max angle left after homing on a stationary shaft in degrees, the zero point has no fraction
+const HOMING_TOLERANCE: f32 = 1.0;
+/// Degrees per second at 1 rpm
+const DEG_PER_S_PER_RPM: f32 = 6.0;
+
 #[derive(Debug)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
-    Timeout,
-    UartError(usart::Error),
-    ParseError(rmodbus::ErrorKind),
+    Modbus(client::Error),
+    InvalidNodeId,
+    /// The angle read back after setting the zero point is not zero
+    HomingFailed,
+}
+
+impl From<client::Error> for Error {
+    fn from(value: client::Error) -> Self {
+        Error::Modbus(value)
+    }
 }
 
 const REGS_COUNT: usize = 7;
 
-pub struct Encoder {
-    uart: &'static Mutex<ThreadModeRawMutex, Rs485<'static>>,
-    node_id: u16,
-    timeout: Duration,
+/// One poll of the encoder registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncoderReading {
+    /// Whole degrees of the shaft angle
+    pub angle_deg: u16,
+    /// Fraction of the shaft angle in 0.01 degrees
+    pub angle_fraction: u16,
+    /// Turn counter
+    pub counter: u16,
+    /// Surface speed in mm/s
+    pub linear_speed: u16,
+    /// Shaft frequency in rpm
+    pub rotation_frequency: u16,
+    pub timestamp: Instant,
 }
 
-impl Encoder {
-    pub fn new(
-        uart: &'static Mutex<ThreadModeRawMutex, Rs485<'static>>,
-        node_id: u16,
-        timeout: Duration
-    ) -> Self {
+impl EncoderReading {
+    fn from_registers(regs: &[u16; REGS_COUNT], timestamp: Instant) -> Self {
         Self {
-            uart, 
-            node_id,
-            timeout,
+            angle_deg: regs[regs::ROTATION_ANGLE as usize],
+            angle_fraction: regs[regs::ROTATION_ANGLE_F as usize],
+            counter: regs[regs::CURRENT_COUNTER as usize],
+            linear_speed: regs[regs::LINEAR_SPEED as usize],
+            rotation_frequency: regs[regs::ROTATION_FRQ as usize],
+            timestamp,
         }
     }
 
-    pub async fn update(&mut self) -> Result<[u16; 5], Error> {
-        let mut mreq = ModbusRequest::new(self.node_id as u8, ModbusProto::Rtu);
-        let mut request: Vec<u8, 256> = Vec::new();
-        let mut response = [0u8; 256];
-        mreq.generate_get_holdings(0, REGS_COUNT as u16, &mut request).unwrap();
+    /// Shaft angle in degrees
+    pub fn angle(&self) -> f32 {
+        self.angle_deg as f32 + self.angle_fraction as f32 * ANGLE_FRACTION_SCALE
+    }
+}
+
+/// RS-485 absolute encoder. Answers are checked for length, echo and exceptions by `ModbusClient`.
+pub struct Encoder<'a, M: RawMutex, T: Transport> {
+    client: ModbusClient<'a, M, T>,
+    node_id: u8,
+}
 
-        let res: select::Either<Result<usize, embassy_stm32::usart::Error>, ()>;
-        {
-            let mut uart = self.uart.lock().await;
-            uart.write(request.as_slice()).await.unwrap();
+impl<'a, M: RawMutex, T: Transport> Encoder<'a, M, T> {
+    pub fn new(client: ModbusClient<'a, M, T>, node_id: u8) -> Self {
+        Self { client, node_id }
+    }
 
-            res = select::select(
-                uart.read_until_idle(&mut response), 
-                Timer::after(self.timeout)
-            ).await;
-        }
+    pub async fn update(&self) -> Result<EncoderReading, Error> {
+        let mut regs = [0u16; REGS_COUNT];
+        self.client.read_holdings(self.node_id, 0, &mut regs).await?;
+        Ok(EncoderReading::from_registers(&regs, Instant::now()))
+    }
 
-        match res {
-            select::Either::First(result) => match result {
-                Ok(count) => {
-                    let mut result: Vec<u16, 7> = Vec::new(); 
-                    mreq.parse_u16(&response[..count], &mut result).map_err(|e| Error::ParseError(e))?;
-                    Ok([result[0], result[1], result[2], result[5], result[6]])
-                },
-                Err(e) => {
-                    Err(Error::UartError(e))
+    /// Poll every `period` and publish the readings into `watch`. Never returns.
+    pub async fn run<WM: RawMutex, const N: usize>(&self, watch: &Watch<WM, EncoderReading, N>, period: Duration) -> ! {
+        let sender = watch.sender();
+        let encoder = Self { client: self.client.with_priority(Priority::Background), node_id: self.node_id };
+        let mut ticker = Ticker::every(period);
+        loop {
+            match encoder.update().await {
+                Ok(reading) => {
+                    trace!("Encoder: {}", reading);
+                    sender.send(reading);
                 },
-            },
-            select::Either::Second(_) => Err(Error::Timeout),
+                Err(e) => warn!("Encoder: {}", e),
+            }
+            ticker.next().await;
         }
     }
 
     async fn set_reg(&self, reg: u16, value: u16) -> Result<(), Error> {
-        let mut mreq = ModbusRequest::new(self.node_id as u8, ModbusProto::Rtu);
-        let mut request: Vec<u8, 256> = Vec::new();
-        let mut response = [0u8; 256];
-        mreq.generate_set_holdings_bulk(reg, &[value as u16], &mut request).unwrap();
-        info!("value: {} ({})", value, defmt::Debug2Format(&request));
-
-        let res: select::Either<Result<usize, embassy_stm32::usart::Error>, ()>;
-        {
-            let mut uart = self.uart.lock().await;
-            uart.write(&request.as_slice()).await.unwrap();
-
-            res = select::select(
-                uart.read_until_idle(&mut response), 
-                Timer::after(self.timeout)
-            ).await;
-        }
-
-        match res {
-            select::Either::First(res) => match res {
-                Ok(_) => Ok(()),
-                Err(e) => Err(Error::UartError(e)),
-            },
-            select::Either::Second(_) => Err(Error::Timeout),
-        }
+        self.client.write_holding(self.node_id, reg, value).await?;
+        Ok(())
     }
 
     async fn reg(&self, reg: u16) -> Result<u16, Error> {
-        let mut mreq = ModbusRequest::new(self.node_id as u8, ModbusProto::Rtu);
-        let mut request: Vec<u8, 256> = Vec::new();
-        let mut response = [0u8; 256];
-        mreq.generate_get_holdings(reg, 1, &mut request).unwrap();
-
-        let res: select::Either<Result<usize, embassy_stm32::usart::Error>, ()>;
-        {
-            let mut uart = self.uart.lock().await;
-            uart.write(&request.as_slice()).await.unwrap();
-
-            res = select::select(
-                uart.read_until_idle(&mut response), 
-                Timer::after(self.timeout)
-            ).await;
-        }
+        let mut value = [0u16; 1];
+        self.client.read_holdings(self.node_id, reg, &mut value).await?;
+        Ok(value[0])
+    }
 
-        match res {
-            select::Either::First(result) => match result {
-                Ok(count) => {
-                    let mut result: Vec<u16, 1> = Vec::new(); 
-                    mreq.parse_u16(&response[..count], &mut result).map_err(|e| Error::ParseError(e))?;
-                    
-                    Ok(result[0])
-                },
-                Err(e) => {
-                    Err(Error::UartError(e))
-                },
-            },
-            select::Either::Second(_) => Err(Error::Timeout),
+    /// Make the current shaft angle the device zero and restart `tracker` from there
+    pub async fn home(&self, tracker: &mut MotionTracker) -> Result<(), Error> {
+        let zero_point = self.zero_point().await?;
+        let reading = self.update().await?;
+        // the device reports angles relative to the zero point, both come from the device and may exceed a turn
+        let zero_point = (zero_point as u32 + reading.angle_deg as u32) % DEGREES_PER_TURN as u32;
+        self.set_zero_point(zero_point as u16).await?;
+
+        let homed = self.update().await?;
+        // a moving shaft turns on between the two readings
+        let elapsed = homed.timestamp.saturating_duration_since(reading.timestamp).as_micros() as f32 / 1_000_000.0;
+        let rpm = reading.rotation_frequency.max(homed.rotation_frequency) as f32;
+        let tolerance = HOMING_TOLERANCE + rpm * DEG_PER_S_PER_RPM * elapsed;
+        let angle = homed.angle();
+        // the shaft may stand just below a full turn
+        let offset = if angle > DEGREES_PER_TURN as f32 / 2.0 { DEGREES_PER_TURN as f32 - angle } else { angle };
+        if offset > tolerance {
+            return Err(Error::HomingFailed);
         }
+        tracker.reset(&homed);
+        Ok(())
+    }
+
+    /// Motion tracker for the shaft diameter configured in the device
+    pub async fn motion_tracker(&self, config: MotionConfig) -> Result<MotionTracker, Error> {
+        let shaft_diameter = self.shaft_diameter().await?;
+        Ok(MotionTracker::new_advanced(shaft_diameter as f32, config))
     }
 
     pub async fn set_zero_point(&self, zero_point: u16) -> Result<(), Error> {
@@ -151,8 +166,11 @@ impl Encoder {
     }
 
     pub async fn set_node_id(&mut self, node_id: u16) -> Result<(), Error> {
+        if !(1..=MAX_NODE_ID).contains(&node_id) {
+            return Err(Error::InvalidNodeId);
+        }
         self.set_reg(regs::NODE_ID, node_id).await?;
-        self.node_id = node_id;
+        self.node_id = node_id as u8;
         Ok(())
     }
 
@@ -167,4 +185,4 @@ impl Encoder {
     pub async fn node_id(&mut self) -> Result<u16, Error> {
         self.reg(regs::NODE_ID).await
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,167 @@
+use core::f64::consts::PI;
+
+use defmt::warn;
+use embassy_sync::{blocking_mutex::raw::RawMutex, watch::{Receiver, Watch}};
+use embassy_time::Instant;
+
+use super::EncoderReading;
+
+const DEGREES_PER_TURN: f64 = 360.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionConfig {
+    /// Smoothing of the velocity and acceleration estimates, 1.0 disables filtering
+    pub filter: f32,
+    /// Allowed difference between the estimated speed and `LINEAR_SPEED` in mm/s
+    pub speed_tolerance: f32,
+    /// Allowed difference relative to `LINEAR_SPEED`, added to `speed_tolerance`
+    pub speed_tolerance_rel: f32,
+}
+
+impl Default for MotionConfig {
+    fn default() -> Self {
+        Self {
+            filter: 0.3,
+            speed_tolerance: 5.0,
+            speed_tolerance_rel: 0.1,
+        }
+    }
+}
+
+/// Conveyor motion derived from the encoder readings
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Motion {
+    /// Shaft turns since homing, fractional part from the angle
+    pub turns: f64,
+    /// Travelled distance since homing in mm
+    pub position: f64,
+    /// Estimated speed in mm/s, negative when running backwards
+    pub velocity: f32,
+    /// Estimated acceleration in mm/s²
+    pub acceleration: f32,
+    /// `LINEAR_SPEED` reported by the device in mm/s
+    pub device_speed: u16,
+    /// `velocity` differs from `device_speed` by more than the tolerance
+    pub speed_mismatch: bool,
+    pub timestamp: Instant,
+}
+
+/// Multi-turn position from the 16 bit turn counter, assumes less than
+/// half a counter range between two readings.
+pub struct MotionTracker {
+    config: MotionConfig,
+    /// Shaft diameter in mm
+    shaft_diameter: f32,
+    last_counter: Option<u16>,
+    /// Unwrapped turn counter
+    counter: i64,
+    /// Turns at the home position
+    origin: f64,
+    last: Option<(f64, Instant)>,
+    velocity: f32,
+    acceleration: f32,
+}
+
+impl MotionTracker {
+    pub fn new(shaft_diameter: f32) -> Self {
+        Self::new_advanced(shaft_diameter, MotionConfig::default())
+    }
+
+    pub fn new_advanced(shaft_diameter: f32, config: MotionConfig) -> Self {
+        Self {
+            config,
+            shaft_diameter,
+            last_counter: None,
+            counter: 0,
+            origin: 0.0,
+            last: None,
+            velocity: 0.0,
+            acceleration: 0.0,
+        }
+    }
+
+    pub fn set_shaft_diameter(&mut self, shaft_diameter: f32) {
+        self.shaft_diameter = shaft_diameter;
+    }
+
+    pub fn shaft_diameter(&self) -> f32 {
+        self.shaft_diameter
+    }
+
+    /// Travelled distance per shaft turn in mm
+    pub fn circumference(&self) -> f64 {
+        PI * self.shaft_diameter as f64
+    }
+
+    /// Make the position of `reading` the new zero
+    pub fn reset(&mut self, reading: &EncoderReading) {
+        self.last_counter = None;
+        self.counter = 0;
+        self.origin = self.absolute_turns(reading);
+        self.last = None;
+        self.velocity = 0.0;
+        self.acceleration = 0.0;
+    }
+
+    pub fn update(&mut self, reading: &EncoderReading) -> Motion {
+        let turns = self.absolute_turns(reading) - self.origin;
+        let position = turns * self.circumference();
+
+        if let Some((last_position, last_time)) = self.last {
+            let dt = reading.timestamp.checked_duration_since(last_time).map(|d| d.as_micros()).unwrap_or(0);
+            if dt > 0 {
+                let dt = dt as f32 / 1_000_000.0;
+                let velocity = ((position - last_position) as f32) / dt;
+                let velocity = self.velocity + (velocity - self.velocity) * self.config.filter;
+                let acceleration = (velocity - self.velocity) / dt;
+                self.acceleration += (acceleration - self.acceleration) * self.config.filter;
+                self.velocity = velocity;
+            }
+        }
+        self.last = Some((position, reading.timestamp));
+
+        let device_speed = reading.linear_speed;
+        let tolerance = self.config.speed_tolerance + self.config.speed_tolerance_rel * device_speed as f32;
+        let speed_mismatch = abs(abs(self.velocity) - device_speed as f32) > tolerance;
+
+        Motion {
+            turns,
+            position,
+            velocity: self.velocity,
+            acceleration: self.acceleration,
+            device_speed,
+            speed_mismatch,
+            timestamp: reading.timestamp,
+        }
+    }
+
+    /// Track the readings from `receiver` and publish the motion into `motion`. Never returns.
+    pub async fn run<RM: RawMutex, MM: RawMutex, const N: usize, const K: usize>(
+        &mut self,
+        mut receiver: Receiver<'_, RM, EncoderReading, N>,
+        motion: &Watch<MM, Motion, K>,
+    ) -> ! {
+        let sender = motion.sender();
+        loop {
+            let reading = receiver.changed().await;
+            let m = self.update(&reading);
+            if m.speed_mismatch {
+                warn!("Encoder: estimated speed {} mm/s, device reports {} mm/s", m.velocity, m.device_speed);
+            }
+            sender.send(m);
+        }
+    }
+
+    fn absolute_turns(&mut self, reading: &EncoderReading) -> f64 {
+        if let Some(last) = self.last_counter {
+            self.counter += reading.counter.wrapping_sub(last) as i16 as i64;
+        }
+        self.last_counter = Some(reading.counter);
+        self.counter as f64 + reading.angle() as f64 / DEGREES_PER_TURN
+    }
+}
+
+fn abs(value: f32) -> f32 {
+    if value < 0.0 { -value } else { value }
+}
@@ -0,0 +1,140 @@
+use defmt::info;
+use embassy_time::{Duration, Instant};
+
+use super::{ModbusSlaves, SlaveNumber};
+
+const SLAVES: usize = 8;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SlaveHealth {
+    /// Not polled yet
+    #[default]
+    Unknown,
+    Online,
+    /// Answering, but some requests failed lately
+    Degraded,
+    /// Polled only by periodic re-probes
+    Offline,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HealthConfig {
+    /// Consecutive failures until a slave is offline, every failure before makes it degraded
+    pub offline_after: u16,
+    /// Successes in a row until a degraded slave is online again
+    pub online_after: u16,
+    /// First re-probe delay of an offline slave, doubled after every failed probe
+    pub probe_delay: Duration,
+    pub max_probe_delay: Duration,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            offline_after: 3,
+            online_after: 3,
+            probe_delay: Duration::from_secs(1),
+            max_probe_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tracker {
+    health: SlaveHealth,
+    failures: u16,
+    successes: u16,
+    probe_delay: Duration,
+    next_probe: Instant,
+}
+
+/// Online/degraded/offline state of the eight `SlaveNumber` slaves
+pub struct SlaveHealthTable {
+    config: HealthConfig,
+    slaves: [Tracker; SLAVES],
+}
+
+impl SlaveHealthTable {
+    pub fn new(config: HealthConfig) -> Self {
+        let tracker = Tracker {
+            health: SlaveHealth::Unknown,
+            failures: 0,
+            successes: 0,
+            probe_delay: config.probe_delay,
+            next_probe: Instant::from_ticks(0),
+        };
+        Self { config, slaves: [tracker; SLAVES] }
+    }
+
+    pub fn health(&self, slave: SlaveNumber) -> SlaveHealth {
+        self.slaves[u8::from(slave) as usize].health
+    }
+
+    /// Earliest time `slave` may be polled, offline slaves wait for their next probe
+    pub fn next_allowed(&self, slave: SlaveNumber) -> Instant {
+        let tracker = &self.slaves[u8::from(slave) as usize];
+        match tracker.health {
+            SlaveHealth::Offline => tracker.next_probe,
+            _ => Instant::from_ticks(0),
+        }
+    }
+
+    /// The slave answered, exceptions count as an answer
+    pub fn on_success(&mut self, slave: SlaveNumber) {
+        let online_after = self.config.online_after;
+        let tracker = &mut self.slaves[u8::from(slave) as usize];
+        tracker.failures = 0;
+        tracker.successes = tracker.successes.saturating_add(1);
+        tracker.probe_delay = self.config.probe_delay;
+        let health = match tracker.health {
+            SlaveHealth::Degraded if tracker.successes < online_after => SlaveHealth::Degraded,
+            // an offline slave answering a probe is degraded until it proved stable
+            SlaveHealth::Offline => SlaveHealth::Degraded,
+            _ => SlaveHealth::Online,
+        };
+        Self::set_health(slave, tracker, health);
+    }
+
+    /// The slave did not answer or the answer was broken
+    pub fn on_failure(&mut self, slave: SlaveNumber) {
+        let config = self.config;
+        let tracker = &mut self.slaves[u8::from(slave) as usize];
+        tracker.successes = 0;
+        tracker.failures = tracker.failures.saturating_add(1);
+        if tracker.health == SlaveHealth::Offline {
+            // failed re-probe, back off further
+            tracker.probe_delay = (tracker.probe_delay * 2).min(config.max_probe_delay);
+        }
+        let health = if tracker.failures >= config.offline_after { SlaveHealth::Offline } else { SlaveHealth::Degraded };
+        if health == SlaveHealth::Offline {
+            tracker.next_probe = Instant::now() + tracker.probe_delay;
+        }
+        Self::set_health(slave, tracker, health);
+    }
+
+    fn set_health(slave: SlaveNumber, tracker: &mut Tracker, health: SlaveHealth) {
+        if tracker.health != health {
+            info!("SlaveHealth: {} {} -> {}", slave, tracker.health, health);
+            tracker.health = health;
+        }
+    }
+
+    /// Bit n of `.0` is set while `SlaveNumber` n answers, bit n of `.1` while it is degraded or offline
+    pub fn status(&self) -> ModbusSlaves {
+        let mut answering = 0u16;
+        let mut failing = 0u16;
+        for (i, tracker) in self.slaves.iter().enumerate() {
+            match tracker.health {
+                SlaveHealth::Online => answering |= 1 << i,
+                SlaveHealth::Degraded => {
+                    answering |= 1 << i;
+                    failing |= 1 << i;
+                },
+                SlaveHealth::Offline => failing |= 1 << i,
+                SlaveHealth::Unknown => {},
+            }
+        }
+        ModbusSlaves(answering, failing)
+    }
+}
@@ -0,0 +1,335 @@
+use defmt::{trace, warn};
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::components::com::transport::Transport;
+use crate::components::server::register_map::{Area, Exception};
+use crate::components::server::register_provider::RegisterProvider;
+use super::bus::Priority;
+use super::client::{self, ExceptionCode, ModbusClient};
+use super::health::{HealthConfig, SlaveHealth, SlaveHealthTable};
+use super::{ModbusSlaves, SlaveNumber};
+
+/// Largest block of coils or discretes a single entry can poll
+pub const MAX_POLL_BITS: usize = 256;
+/// Largest block of registers a single entry can poll
+pub const MAX_POLL_REGISTERS: usize = 123;
+const SLAVES: usize = 8;
+/// Wait time while no entry belongs to an enabled slave
+const IDLE_PERIOD: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollAction {
+    /// Copy the `remote` area of the slave into the `local` area
+    Read { remote: Area, local: Area },
+    /// Write local coils or holdings into the same area of the slave whenever they changed
+    WriteOnChange { area: Area },
+}
+
+/// One line of the poll table
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PollEntry {
+    pub slave: SlaveNumber,
+    pub action: PollAction,
+    pub remote_start: u16,
+    pub local_start: u16,
+    pub count: u16,
+    pub period: Duration,
+}
+
+impl PollEntry {
+    pub const fn read(slave: SlaveNumber, remote: Area, remote_start: u16, local: Area, local_start: u16, count: u16, period: Duration) -> Self {
+        Self { slave, action: PollAction::Read { remote, local }, remote_start, local_start, count, period }
+    }
+
+    /// Local registers are compared every `period`
+    pub const fn write_on_change(slave: SlaveNumber, area: Area, local_start: u16, remote_start: u16, count: u16, period: Duration) -> Self {
+        Self { slave, action: PollAction::WriteOnChange { area }, remote_start, local_start, count, period }
+    }
+
+    fn is_bits(&self) -> bool {
+        match self.action {
+            PollAction::Read { remote, .. } => matches!(remote, Area::Coil | Area::Discrete),
+            PollAction::WriteOnChange { area } => matches!(area, Area::Coil | Area::Discrete),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PollError {
+    Timeout,
+    /// Broken frame, UART error or unexpected answer
+    Communication,
+    Exception(ExceptionCode),
+    /// Local storage rejected the registers
+    Local(Exception),
+    /// Entry does not describe a valid request
+    InvalidEntry,
+}
+
+impl From<client::Error> for PollError {
+    fn from(value: client::Error) -> Self {
+        match value {
+            client::Error::Timeout => PollError::Timeout,
+            client::Error::Exception(e) => PollError::Exception(e),
+            client::Error::InvalidRequest => PollError::InvalidEntry,
+            _ => PollError::Communication,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EntryStatus {
+    pub polls: u32,
+    pub errors: u32,
+    pub consecutive_errors: u16,
+    pub last_success: Option<Instant>,
+    pub last_error: Option<PollError>,
+}
+
+/// Cyclic master, mirrors remote slaves into the local storage as described by a poll table.
+/// Only entries of slaves enabled in `ModbusSlaves` (bit n for `SlaveNumber` n) are polled,
+/// offline slaves are only re-probed with a growing delay so they do not slow down the bus.
+pub struct ModbusPoller<'a, MB: RawMutex, T: Transport, MS: RawMutex, P: RegisterProvider, const N: usize> {
+    client: ModbusClient<'a, MB, T>,
+    storage: &'a Mutex<MS, P>,
+    entries: &'a [PollEntry; N],
+    unit_ids: [u8; SLAVES],
+    enabled: ModbusSlaves,
+    enabled_registers: Option<u16>,
+    next_due: [Instant; N],
+    /// Values last written by `WriteOnChange` entries, coils packed 16 to a word
+    written: [Option<[u16; MAX_POLL_REGISTERS]>; N],
+    status: [EntryStatus; N],
+    health: SlaveHealthTable,
+    health_registers: Option<u16>,
+}
+
+impl<'a, MB: RawMutex, T: Transport, MS: RawMutex, P: RegisterProvider, const N: usize> ModbusPoller<'a, MB, T, MS, P, N> {
+    /// `unit_ids[n]` is the address of `SlaveNumber` n. Requests run at `Priority::Background`.
+    pub fn new(client: ModbusClient<'a, MB, T>, storage: &'a Mutex<MS, P>, entries: &'a [PollEntry; N], unit_ids: [u8; SLAVES], enabled: ModbusSlaves) -> Self {
+        Self {
+            client: client.with_priority(Priority::Background),
+            storage,
+            entries,
+            unit_ids,
+            enabled,
+            enabled_registers: None,
+            next_due: [Instant::now(); N],
+            written: [None; N],
+            status: [EntryStatus::default(); N],
+            health: SlaveHealthTable::new(HealthConfig::default()),
+            health_registers: None,
+        }
+    }
+
+    pub fn set_health_config(&mut self, config: HealthConfig) {
+        self.health = SlaveHealthTable::new(config);
+    }
+
+    /// Mirror `SlaveHealthTable::status` into two local input registers, high word first
+    pub fn set_health_registers(&mut self, start: u16) {
+        self.health_registers = Some(start);
+    }
+
+    pub fn health(&self, slave: SlaveNumber) -> SlaveHealth {
+        self.health.health(slave)
+    }
+
+    pub fn health_status(&self) -> ModbusSlaves {
+        self.health.status()
+    }
+
+    pub fn set_enabled(&mut self, enabled: ModbusSlaves) {
+        self.enabled = enabled;
+    }
+
+    /// Take the enabled slaves from two local holding registers, high word first
+    pub fn set_enabled_registers(&mut self, start: u16) {
+        self.enabled_registers = Some(start);
+    }
+
+    pub fn enabled(&self) -> ModbusSlaves {
+        self.enabled
+    }
+
+    pub fn status(&self) -> &[EntryStatus; N] {
+        &self.status
+    }
+
+    pub fn is_enabled(&self, slave: SlaveNumber) -> bool {
+        self.enabled.0 & (1 << u8::from(slave)) != 0
+    }
+
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.poll_next().await;
+        }
+    }
+
+    /// Wait for the next due entry and run it
+    pub async fn poll_next(&mut self) {
+        self.refresh_enabled().await;
+        let next = (0..N)
+            .filter(|&i| self.is_enabled(self.entries[i].slave))
+            .min_by_key(|&i| self.due(i));
+        let Some(index) = next else {
+            Timer::after(IDLE_PERIOD).await;
+            return;
+        };
+        Timer::at(self.due(index)).await;
+        self.poll(index).await;
+    }
+
+    fn due(&self, index: usize) -> Instant {
+        self.next_due[index].max(self.health.next_allowed(self.entries[index].slave))
+    }
+
+    /// Run entry `index` right away
+    pub async fn poll(&mut self, index: usize) {
+        let entry = self.entries[index];
+        let res = self.execute(index).await;
+        match res {
+            Ok(()) | Err(PollError::Exception(_)) => {
+                // an offline or never heard from slave may have lost its registers
+                let returned = matches!(self.health.health(entry.slave), SlaveHealth::Offline | SlaveHealth::Unknown);
+                self.health.on_success(entry.slave);
+                if returned {
+                    self.invalidate_written(entry.slave, res.is_ok().then_some(index));
+                }
+            },
+            Err(PollError::Timeout | PollError::Communication) => self.health.on_failure(entry.slave),
+            Err(_) => {},
+        }
+        self.write_health().await;
+        let status = &mut self.status[index];
+        status.polls = status.polls.wrapping_add(1);
+        match res {
+            Ok(()) => {
+                status.consecutive_errors = 0;
+                status.last_success = Some(Instant::now());
+            },
+            Err(e) => {
+                warn!("ModbusPoller: entry {} slave {} {}", index, entry.slave, e);
+                status.errors = status.errors.wrapping_add(1);
+                status.consecutive_errors = status.consecutive_errors.saturating_add(1);
+                status.last_error = Some(e);
+            },
+        }
+        // skip missed periods instead of catching up
+        let next = self.next_due[index] + entry.period;
+        self.next_due[index] = next.max(Instant::now());
+    }
+
+    /// Force `WriteOnChange` entries of `slave` to write again, a slave coming back may have lost
+    /// its registers. `keep` is an entry that just wrote successfully.
+    fn invalidate_written(&mut self, slave: SlaveNumber, keep: Option<usize>) {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if entry.slave == slave && Some(i) != keep {
+                self.written[i] = None;
+            }
+        }
+    }
+
+    async fn write_health(&self) {
+        let Some(start) = self.health_registers else {
+            return;
+        };
+        let status = u32::from(self.health.status());
+        let mut storage = self.storage.lock().await;
+        let res = storage.set_input(start, (status >> 16) as u16)
+            .and_then(|_| storage.set_input(start.wrapping_add(1), status as u16));
+        if let Err(e) = res {
+            warn!("ModbusPoller: health registers {}", e);
+        }
+    }
+
+    async fn refresh_enabled(&mut self) {
+        let Some(start) = self.enabled_registers else {
+            return;
+        };
+        let storage = self.storage.lock().await;
+        if let (Ok(high), Ok(low)) = (storage.holding(start), storage.holding(start.wrapping_add(1))) {
+            self.enabled = ModbusSlaves::from((high as u32) << 16 | low as u32);
+        }
+    }
+
+    async fn execute(&mut self, index: usize) -> Result<(), PollError> {
+        let entry = self.entries[index];
+        let unit_id = self.unit_ids[u8::from(entry.slave) as usize];
+        let count = entry.count as usize;
+        if count == 0 || count > if entry.is_bits() { MAX_POLL_BITS } else { MAX_POLL_REGISTERS } {
+            return Err(PollError::InvalidEntry);
+        }
+        match entry.action {
+            PollAction::Read { remote, local } => {
+                let mut words = [0u16; MAX_POLL_BITS];
+                let mut bits = [false; MAX_POLL_BITS];
+                match remote {
+                    Area::Coil => self.client.read_coils(unit_id, entry.remote_start, &mut bits[..count]).await?,
+                    Area::Discrete => self.client.read_discretes(unit_id, entry.remote_start, &mut bits[..count]).await?,
+                    Area::Holding => self.client.read_holdings(unit_id, entry.remote_start, &mut words[..count]).await?,
+                    Area::Input => self.client.read_inputs(unit_id, entry.remote_start, &mut words[..count]).await?,
+                }
+                if entry.is_bits() {
+                    words.iter_mut().zip(bits.iter()).for_each(|(w, &b)| *w = b as u16);
+                }
+                trace!("ModbusPoller: slave {} {}", unit_id, &words[..count]);
+                let mut storage = self.storage.lock().await;
+                for (reg, &value) in (entry.local_start..).zip(&words[..count]) {
+                    let res = match local {
+                        Area::Coil => storage.set_coil(reg, value != 0),
+                        Area::Discrete => storage.set_discrete(reg, value != 0),
+                        Area::Holding => storage.set_holding(reg, value),
+                        Area::Input => storage.set_input(reg, value),
+                    };
+                    res.map_err(|e| PollError::Local(e))?;
+                }
+                Ok(())
+            },
+            PollAction::WriteOnChange { area } => {
+                let mut words = [0u16; MAX_POLL_BITS];
+                {
+                    let storage = self.storage.lock().await;
+                    for (reg, word) in (entry.local_start..).zip(words[..count].iter_mut()) {
+                        *word = match area {
+                            Area::Coil => storage.coil(reg).map(|v| v as u16),
+                            Area::Holding => storage.holding(reg),
+                            _ => return Err(PollError::InvalidEntry),
+                        }
+                        .map_err(|e| PollError::Local(e))?;
+                    }
+                }
+                let values = pack(&words[..count], entry.is_bits());
+                if self.written[index] == Some(values) {
+                    return Ok(());
+                }
+                match area {
+                    Area::Coil => {
+                        let mut bits = [false; MAX_POLL_BITS];
+                        bits.iter_mut().zip(words.iter()).for_each(|(b, &w)| *b = w != 0);
+                        self.client.write_coils(unit_id, entry.remote_start, &bits[..count]).await?
+                    },
+                    _ => self.client.write_holdings(unit_id, entry.remote_start, &words[..count]).await?,
+                }
+                self.written[index] = Some(values);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Copy of the values of a `WriteOnChange` entry, bits are packed so `MAX_POLL_BITS` coils fit
+fn pack(words: &[u16], bits: bool) -> [u16; MAX_POLL_REGISTERS] {
+    let mut values = [0u16; MAX_POLL_REGISTERS];
+    if bits {
+        for (i, &word) in words.iter().enumerate() {
+            values[i / 16] |= ((word != 0) as u16) << (i % 16);
+        }
+    } else {
+        values[..words.len()].copy_from_slice(words);
+    }
+    values
+}
@@ -0,0 +1,129 @@
+use defmt::{info, warn};
+use embassy_stm32::usart;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+use crate::components::com::serial_config::SerialSettings;
+use crate::components::com::transport::Transport;
+use crate::components::server::modbus_link::{rtu::RtuTiming, ModbusMode};
+use super::bus::{ExclusiveSession, ModbusBus, Priority};
+use super::client::{Error, ModbusClient};
+
+const MAX_UNIT_ID: u8 = 247;
+const ID_STRING_SIZE: usize = 32;
+
+/// Basic objects of FC 43 / MEI 14, longer values are cut
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DeviceInfo {
+    pub vendor_name: String<ID_STRING_SIZE>,
+    pub product_code: String<ID_STRING_SIZE>,
+    pub revision: String<ID_STRING_SIZE>,
+}
+
+impl DeviceInfo {
+    /// Decode the answer PDU of a basic device identification request
+    pub fn parse(pdu: &[u8]) -> Option<Self> {
+        let mut info = DeviceInfo::default();
+        let objects = *pdu.get(6)?;
+        let mut pos = 7;
+        for _ in 0..objects {
+            let id = *pdu.get(pos)?;
+            let len = *pdu.get(pos + 1)? as usize;
+            let value = core::str::from_utf8(pdu.get(pos + 2..pos + 2 + len)?).unwrap_or("");
+            let field = match id {
+                0x00 => &mut info.vendor_name,
+                0x01 => &mut info.product_code,
+                0x02 => &mut info.revision,
+                _ => {
+                    pos += 2 + len;
+                    continue;
+                },
+            };
+            for c in value.chars() {
+                if field.push(c).is_err() {
+                    break;
+                }
+            }
+            pos += 2 + len;
+        }
+        Some(info)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanResult {
+    pub unit_id: u8,
+    pub settings: SerialSettings,
+    /// `None` if the device does not support FC 43
+    pub identification: Option<DeviceInfo>,
+}
+
+/// Finds the devices on a serial line by probing every unit id, optionally at several serial settings
+pub struct BusScanner<'a, M: RawMutex, T: Transport> {
+    bus: &'a ModbusBus<M, T>,
+    base: usart::Config,
+    timeout: Duration,
+    first_id: u8,
+    last_id: u8,
+}
+
+impl<'a, M: RawMutex, T: Transport> BusScanner<'a, M, T> {
+    /// `base` is the configuration restored on the bus once the scan is done
+    pub fn new(bus: &'a ModbusBus<M, T>, base: usart::Config) -> Self {
+        Self::new_advanced(bus, base, Duration::from_millis(50), 1, MAX_UNIT_ID)
+    }
+
+    pub fn new_advanced(bus: &'a ModbusBus<M, T>, base: usart::Config, timeout: Duration, first_id: u8, last_id: u8) -> Self {
+        Self { bus, base, timeout, first_id: first_id.max(1), last_id: last_id.min(MAX_UNIT_ID) }
+    }
+
+    /// Probe all unit ids at each of `settings`, at most `N` devices are reported. The bus is
+    /// reserved for the whole scan, other clients wait until the base config is restored.
+    pub async fn scan<const N: usize>(&self, settings: &[SerialSettings]) -> Vec<ScanResult, N> {
+        let mut results = Vec::new();
+        let session = self.bus.exclusive().await;
+        'scan: for &setting in settings {
+            if let Err(e) = self.set_config(&session, &setting.config(self.base)).await {
+                warn!("BusScanner: {} baud rejected {}", setting.baudrate, defmt::Debug2Format(&e));
+                continue;
+            }
+            info!("BusScanner: scanning at {} baud", setting.baudrate);
+            let client = ModbusClient::new_advanced(self.bus, ModbusMode::rtu(setting.baudrate), self.timeout, 0).with_session(&session);
+            for unit_id in self.first_id..=self.last_id {
+                if !probe(&client, unit_id).await {
+                    continue;
+                }
+                let identification = match client.read_device_identification(unit_id, 0x01, 0x00).await {
+                    Ok(pdu) => DeviceInfo::parse(&pdu),
+                    Err(_) => None,
+                };
+                info!("BusScanner: found {} {}", unit_id, identification);
+                if results.push(ScanResult { unit_id, settings: setting, identification }).is_err() {
+                    warn!("BusScanner: result list full");
+                    break 'scan;
+                }
+            }
+        }
+        if let Err(e) = self.set_config(&session, &self.base).await {
+            warn!("BusScanner: restoring bus config {}", defmt::Debug2Format(&e));
+        }
+        results
+    }
+
+    /// Reconfigure the line between two transactions of `session` and adapt the frame gap
+    async fn set_config(&self, session: &ExclusiveSession<'_, M, T>, config: &usart::Config) -> Result<(), usart::ConfigError> {
+        let mut bus = self.bus.acquire_in(Priority::Urgent, Some(session.id())).await;
+        bus.set_config(config)?;
+        let timing = RtuTiming::from_baudrate(config.baudrate);
+        self.bus.set_frame_gap(timing.char_time + timing.t35);
+        Ok(())
+    }
+}
+
+/// Any answer, exceptions included, means a device is listening on `unit_id`
+async fn probe<M: RawMutex, T: Transport>(client: &ModbusClient<'_, M, T>, unit_id: u8) -> bool {
+    let mut value = [0u16; 1];
+    matches!(client.read_holdings(unit_id, 0, &mut value).await, Ok(()) | Err(Error::Exception(_)))
+}
@@ -1,52 +1,557 @@
-use defmt::{trace, Debug2Format};
-use embassy_stm32::usart::{self, Uart};
+use defmt::{info, trace, warn, Debug2Format};
+use embassy_stm32::{gpio::Input, usart};
 use embassy_sync::mutex::Mutex;
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{with_deadline, with_timeout, Duration, Instant};
 use heapless::Vec;
-use rmodbus::{server::{storage::ModbusStorage, ModbusFrame}, ModbusFrameBuf, ModbusProto};
+use ::num::{PrimInt, ToPrimitive};
+use rmodbus::{ModbusFrameBuf, ModbusProto};
+
+use crate::components::com::serial_config::{SerialSettings, SERIAL_REGISTERS};
+use crate::components::com::transport::Transport;
+use crate::components::io::input::DigitalInputGroup;
+use super::modbus_link::{rtu, FrameError, ModbusMode};
+use super::register_map::{RegisterMap, WriteRequest};
+use super::register_provider::RegisterProvider;
+use device_id::{DeviceIdentification, FC_ENCAPSULATED_INTERFACE, MEI_READ_DEVICE_ID};
+use diagnostics::{Diagnostics, DiagnosticsResponse};
+use stats::ServerStats;
+
+pub mod device_id;
+pub mod diagnostics;
+pub mod pdu;
+pub mod stats;
 
 const MODBUS_BUF_SIZE: usize = 256;
+const BROADCAST_ID: u8 = 0;
+const MAX_UNIT_ID: u8 = 247;
+const TCP_PDU_OFFSET: usize = 7;
+/// Line silence that ends the resync after an UART error in ASCII mode
+const ASCII_RESYNC_IDLE: Duration = Duration::from_millis(10);
+const MAX_RESYNC_ATTEMPTS: usize = 8;
+/// Changed serial settings are reverted unless a request arrives within this time
+const SERIAL_CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Debug)]
 pub enum Error {
     ModbusProcess(rmodbus::ErrorKind),
-    Uart(usart::Error)
+    Uart(usart::Error),
+    /// Request dropped because of bad framing or CRC
+    Frame(FrameError),
+}
+
+impl From<FrameError> for Error {
+    fn from(value: FrameError) -> Self {
+        match value {
+            FrameError::Uart(e) => Error::Uart(e),
+            e => Error::Frame(e),
+        }
+    }
+}
+
+/// Source of a slave address, e.g. a DIP switch
+pub trait AddressSwitch {
+    fn address(&self) -> u8;
+}
+
+impl<const INPUTS_SIZE: usize, M: RawMutex, T: 'static + PrimInt, const RECEIVER_SIZE: usize> AddressSwitch for DigitalInputGroup<'_, '_, INPUTS_SIZE, M, T, RECEIVER_SIZE> {
+    fn address(&self) -> u8 {
+        self.update().to_u8().unwrap_or(BROADCAST_ID)
+    }
+}
+
+/// Input forcing the factory serial settings, e.g. a DIP switch
+pub trait FactoryReset {
+    fn is_active(&self) -> bool;
+}
+
+/// Active while pulled low
+impl FactoryReset for Input<'_> {
+    fn is_active(&self) -> bool {
+        self.is_low()
+    }
+}
+
+/// Serial settings mirrored in holding registers
+struct SerialConfig {
+    start: u16,
+    base: usart::Config,
+    current: SerialSettings,
+    /// Settings restored unless a request arrives before the deadline
+    fallback: Option<(SerialSettings, Instant)>,
+}
+
+/// Where a slave takes its unit id from, it is resolved again for every request
+#[derive(Clone, Copy)]
+pub enum UnitId<'a> {
+    Fixed(u8),
+    /// Holding register of the slave's own storage
+    Holding(u16),
+    Switch(&'a dyn AddressSwitch),
+}
+
+pub struct Slave<'a, M: RawMutex, P: RegisterProvider> {
+    pub unit_id: UnitId<'a>,
+    pub storage: &'a Mutex<M, P>,
+    pub register_map: Option<&'a RegisterMap<'a>>,
+}
+
+impl<'a, M: RawMutex, P: RegisterProvider> Slave<'a, M, P> {
+    pub fn new(unit_id: UnitId<'a>, storage: &'a Mutex<M, P>) -> Self {
+        Self { unit_id, storage, register_map: None }
+    }
+
+    pub fn with_register_map(mut self, register_map: &'a RegisterMap<'a>) -> Self {
+        self.register_map = Some(register_map);
+        self
+    }
+
+    /// Current unit id, `None` while it is out of the 1..=247 range
+    pub async fn resolve_unit_id(&self) -> Option<u8> {
+        let id = match self.unit_id {
+            UnitId::Fixed(id) => id,
+            UnitId::Holding(reg) => {
+                let storage = self.storage.lock().await;
+                storage.holding(reg).ok().and_then(|v| u8::try_from(v).ok())?
+            },
+            UnitId::Switch(switch) => switch.address(),
+        };
+        (1..=MAX_UNIT_ID).contains(&id).then_some(id)
+    }
 }
 
-pub struct ModbusServer<const C: usize, const D: usize, const I: usize, const H: usize> {
-    uart: Uart<'static, embassy_stm32::mode::Async>,
-    storage: &'static Mutex<ThreadModeRawMutex, ModbusStorage<C,D,I,H>>,
+/// Modbus RTU/ASCII slave over any `Transport`, e.g. a bare `Uart` or `Rs485` with DE control.
+/// Besides its own slave it can answer for several virtual slaves on the same bus.
+/// Registers come from any `RegisterProvider`, `ModbusStorage` being the default one.
+pub struct ModbusServer<'a, T: Transport, M: RawMutex, P: RegisterProvider> {
+    port: T,
+    slave: Slave<'a, M, P>,
+    virtual_slaves: &'a [Slave<'a, M, P>],
+    mode: ModbusMode,
+    diagnostics: Diagnostics,
+    identification: Option<&'a DeviceIdentification<'a>>,
+    stats: ServerStats,
+    stats_registers: Option<u16>,
+    serial: Option<SerialConfig>,
 }
 
-impl<const C: usize, const D: usize, const I: usize, const H: usize> ModbusServer<C,D,I,H> {
-    pub fn new(uart: Uart<'static, embassy_stm32::mode::Async>, storage: &'static Mutex<ThreadModeRawMutex, ModbusStorage<C,D,I,H>>) -> Self {
+impl<'a, T: Transport, M: RawMutex, P: RegisterProvider> ModbusServer<'a, T, M, P> {
+    pub fn new(port: T, storage: &'a Mutex<M, P>, unit_id: UnitId<'a>, mode: ModbusMode) -> Self {
         Self {
-            uart,
-            storage,
+            port,
+            slave: Slave::new(unit_id, storage),
+            virtual_slaves: &[],
+            mode,
+            diagnostics: Diagnostics::new(),
+            identification: None,
+            stats: ServerStats::new(),
+            stats_registers: None,
+            serial: None,
         }
     }
 
-    pub async fn update(&mut self, id: u8) -> Result<(), Error> {
+    /// Answer FC 43 / MEI 14 requests with `identification`
+    pub fn set_device_identification(&mut self, identification: &'a DeviceIdentification<'a>) {
+        self.identification = Some(identification);
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// Mirror `ServerStats` into the input registers of the own slave starting at `start`
+    pub fn set_stats_registers(&mut self, start: u16) {
+        self.stats_registers = Some(start);
+    }
+
+    /// Take baud rate, parity and stop bits from the `SERIAL_REGISTERS` holding registers at `start` of the own slave.
+    /// Startup settings are read from the registers, `factory` is used instead if they are invalid or `reset` is active.
+    /// A change is applied after the response to the write and reverted unless a request arrives within 30 s at the new settings.
+    pub async fn set_serial_config(&mut self, start: u16, base: usart::Config, factory: SerialSettings, reset: Option<&dyn FactoryReset>) {
+        let settings = match reset {
+            Some(reset) if reset.is_active() => {
+                info!("ModbusServer: factory serial settings");
+                factory
+            },
+            _ => self.read_serial_registers(start).await.and_then(SerialSettings::from_registers).unwrap_or(factory),
+        };
+        self.serial = Some(SerialConfig { start, base, current: factory, fallback: None });
+        if !self.apply_serial_settings(settings) && settings != factory {
+            self.apply_serial_settings(factory);
+        }
+        self.write_serial_registers().await;
+    }
+
+    pub fn set_unit_id(&mut self, unit_id: UnitId<'a>) {
+        self.slave.unit_id = unit_id;
+    }
+
+    /// Check every write against `register_map` before it reaches the storage
+    pub fn set_register_map(&mut self, register_map: &'a RegisterMap<'a>) {
+        self.slave.register_map = Some(register_map);
+    }
+
+    /// Answer for `slaves` as well, each one with its own unit id and storage
+    pub fn set_virtual_slaves(&mut self, slaves: &'a [Slave<'a, M, P>]) {
+        self.virtual_slaves = slaves;
+    }
+
+    /// Serve requests forever. Errors are counted in `stats`, the line is resynchronized after UART errors.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            match self.update().await {
+                Ok(()) => {},
+                Err(e @ (Error::Uart(_) | Error::Frame(FrameError::Broken | FrameError::Overflow))) => {
+                    warn!("ModbusServer: {}", Debug2Format(&e));
+                    self.resync().await;
+                },
+                Err(e) => trace!("ModbusServer: {}", Debug2Format(&e)),
+            }
+        }
+    }
+
+    /// Handle one frame
+    pub async fn update(&mut self) -> Result<(), Error> {
+        let res = self.process_frame().await;
+        if let Err(e) = &res {
+            self.stats.on_error(e);
+        }
+        if let Some(start) = self.stats_registers {
+            let mut storage = self.slave.storage.lock().await;
+            for (reg, value) in (start..).zip(self.stats.to_registers()) {
+                if let Err(e) = storage.set_input(reg, value) {
+                    warn!("ModbusServer: stats register {} {}", reg, e);
+                    break;
+                }
+            }
+        }
+        res
+    }
+
+    /// Drop the rest of a corrupted frame, returns once the line is silent
+    async fn resync(&mut self) {
+        let idle = match self.mode {
+            ModbusMode::Rtu(timing) => timing.char_time + timing.t35,
+            ModbusMode::Ascii => ASCII_RESYNC_IDLE,
+        };
+        let mut buf = [0u8; MODBUS_BUF_SIZE];
+        for _ in 0..MAX_RESYNC_ATTEMPTS {
+            if with_timeout(idle, self.port.read_until_idle(&mut buf)).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    async fn process_frame(&mut self) -> Result<(), Error> {
         let mut buf: ModbusFrameBuf = [0; MODBUS_BUF_SIZE];
-        let count = self.uart.read_until_idle(&mut buf).await.map_err(|e| Error::Uart(e))?;
+        let deadline = self.serial.as_ref().and_then(|s| s.fallback).map(|(_, deadline)| deadline);
+        let read = self.mode.read_frame(&mut self.port, &mut buf);
+        let res = match deadline {
+            Some(deadline) => match with_deadline(deadline, read).await {
+                Ok(res) => res,
+                Err(_) => {
+                    self.revert_serial_settings().await;
+                    return Ok(());
+                },
+            },
+            None => read.await,
+        };
+        let count = match res {
+            Ok(count) => count,
+            Err(e) => {
+                self.diagnostics.on_frame_error(&e);
+                return Err(e.into());
+            },
+        };
         trace!("ModbusServer: RX {}", &buf[..count]);
+
+        let id = buf[0];
+        if id == BROADCAST_ID {
+            self.diagnostics.on_message(false, true);
+            self.stats.on_broadcast();
+            if !self.diagnostics.listen_only() {
+                self.process_broadcast(&mut buf[..count]).await;
+                self.update_serial_settings().await;
+            }
+            return Ok(());
+        }
+        let Some(slave) = self.find_slave(id).await else {
+            self.diagnostics.on_message(false, false);
+            self.stats.on_ignored();
+            return Ok(());
+        };
+        let storage = slave.storage;
+        let register_map = slave.register_map;
+        self.diagnostics.on_message(true, false);
+        self.stats.on_request();
+        // the master reached us, changed serial settings are kept
+        if let Some(serial) = self.serial.as_mut() {
+            serial.fallback = None;
+        }
+
+        let request = &buf[..count];
+        let func = request[1];
+        // in listen only mode nothing is answered, restart is the only request processed
+        if self.diagnostics.listen_only() && !Diagnostics::is_restart_request(request_pdu(request, ModbusProto::Rtu)) {
+            return Ok(());
+        }
         let mut response: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
-        let mut frame = ModbusFrame::new(id, &buf[..count], ModbusProto::Rtu, &mut response);
-        frame.parse().map_err(|e| Error::ModbusProcess(e))?;
-        if frame.processing_required {
-            if frame.readonly {
-                let storage_locked = self.storage.lock().await;
-                frame.process_read(&*storage_locked).map_err(|e| Error::ModbusProcess(e))?;
-            } else {
-                let mut storage_locked = self.storage.lock().await;
-                frame.process_write(&mut *storage_locked).map_err(|e| Error::ModbusProcess(e))?;
+        let respond = if Diagnostics::is_diagnostics_function(func) {
+            self.process_diagnostics(request, &mut response)?
+        } else if func == FC_ENCAPSULATED_INTERFACE && request.get(2) == Some(&MEI_READ_DEVICE_ID) {
+            self.process_device_identification(request, &mut response)?;
+            true
+        } else {
+            process_request(id, request, ModbusProto::Rtu, storage, register_map, &mut response).await?
+        };
+        if !respond {
+            self.diagnostics.on_no_response();
+            return Ok(());
+        }
+        let exception = (response.get(1).is_some_and(|f| f & 0x80 != 0)).then(|| response.get(2).copied().unwrap_or(0));
+        self.diagnostics.on_response(func, exception);
+        self.stats.on_response(exception.is_some());
+        trace!("ModbusServer: TX {}", Debug2Format(&response));
+        self.mode.write_frame(&mut self.port, response.as_slice()).await?;
+        self.update_serial_settings().await;
+        Ok(())
+    }
+
+    async fn read_serial_registers(&self, start: u16) -> Option<[u16; SERIAL_REGISTERS]> {
+        let storage = self.slave.storage.lock().await;
+        let mut registers = [0u16; SERIAL_REGISTERS];
+        for (reg, value) in (start..).zip(registers.iter_mut()) {
+            *value = storage.holding(reg).ok()?;
+        }
+        Some(registers)
+    }
+
+    async fn write_serial_registers(&self) {
+        let Some(serial) = self.serial.as_ref() else {
+            return;
+        };
+        let mut storage = self.slave.storage.lock().await;
+        for (reg, value) in (serial.start..).zip(serial.current.to_registers()) {
+            if let Err(e) = storage.set_holding(reg, value) {
+                warn!("ModbusServer: serial register {} {}", reg, e);
+                return;
+            }
+        }
+    }
+
+    /// Reconfigure the port, returns `false` if the UART rejected `settings`
+    fn apply_serial_settings(&mut self, settings: SerialSettings) -> bool {
+        let Some(serial) = self.serial.as_mut() else {
+            return false;
+        };
+        if let Err(e) = self.port.set_config(&settings.config(serial.base)) {
+            warn!("ModbusServer: serial settings {} rejected {}", settings.baudrate, Debug2Format(&e));
+            return false;
+        }
+        serial.current = settings;
+        if let ModbusMode::Rtu(_) = self.mode {
+            self.mode = ModbusMode::rtu(settings.baudrate);
+        }
+        true
+    }
+
+    /// Apply changed serial registers, invalid values are overwritten with the current settings
+    async fn update_serial_settings(&mut self) {
+        let Some(serial) = self.serial.as_ref() else {
+            return;
+        };
+        let (start, previous) = (serial.start, serial.current);
+        let registers = self.read_serial_registers(start).await;
+        if registers == Some(previous.to_registers()) {
+            return;
+        }
+        // the response is still sent at the old settings, let it leave the wire before reconfiguring
+        if let Err(e) = self.port.flush().await {
+            warn!("ModbusServer: flush {}", Debug2Format(&e));
+        }
+        match registers.and_then(SerialSettings::from_registers) {
+            Some(settings) if self.apply_serial_settings(settings) => {
+                info!("ModbusServer: serial settings changed to {} baud", settings.baudrate);
+                if let Some(serial) = self.serial.as_mut() {
+                    serial.fallback = Some((previous, Instant::now() + SERIAL_CONFIRM_TIMEOUT));
+                }
+            },
+            _ => self.write_serial_registers().await,
+        }
+    }
+
+    /// No request arrived at the new settings
+    async fn revert_serial_settings(&mut self) {
+        let Some((previous, _)) = self.serial.as_mut().and_then(|s| s.fallback.take()) else {
+            return;
+        };
+        warn!("ModbusServer: no request after serial settings change, back to {} baud", previous.baudrate);
+        self.apply_serial_settings(previous);
+        self.write_serial_registers().await;
+    }
+
+    fn process_device_identification(&self, request: &[u8], response: &mut Vec<u8, MODBUS_BUF_SIZE>) -> Result<(), Error> {
+        let mut pdu: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
+        let res = match self.identification {
+            Some(identification) => identification.process(request_pdu(request, ModbusProto::Rtu), &mut pdu),
+            None => Err(0x01),
+        };
+        match res {
+            Ok(()) => rtu_response(request[0], &pdu, response),
+            Err(code) => exception_response(request, ModbusProto::Rtu, code, response),
+        }
+    }
+
+    /// FC 08, 11 and 12 are answered from the server statistics
+    fn process_diagnostics(&mut self, request: &[u8], response: &mut Vec<u8, MODBUS_BUF_SIZE>) -> Result<bool, Error> {
+        let listen_only = self.diagnostics.listen_only();
+        let mut pdu: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
+        match self.diagnostics.process(request_pdu(request, ModbusProto::Rtu), &mut pdu) {
+            // a restart received in listen only mode is not answered, even with bad data
+            _ if listen_only => Ok(false),
+            DiagnosticsResponse::Pdu => {
+                rtu_response(request[0], &pdu, response)?;
+                Ok(true)
+            },
+            DiagnosticsResponse::NoResponse => Ok(false),
+            DiagnosticsResponse::Exception(code) => {
+                exception_response(request, ModbusProto::Rtu, code, response)?;
+                Ok(true)
+            },
+        }
+    }
+
+    async fn find_slave(&self, id: u8) -> Option<&Slave<'a, M, P>> {
+        for slave in core::iter::once(&self.slave).chain(self.virtual_slaves.iter()) {
+            if slave.resolve_unit_id().await == Some(id) {
+                return Some(slave);
+            }
+        }
+        None
+    }
+
+    /// Broadcast writes are applied to every slave and never answered
+    async fn process_broadcast(&self, request: &mut [u8]) {
+        if request.len() < rtu::MIN_FRAME_SIZE || !is_write_function(request[1]) {
+            return;
+        }
+        for slave in core::iter::once(&self.slave).chain(self.virtual_slaves.iter()) {
+            let Some(id) = slave.resolve_unit_id().await else {
+                continue;
             };
+            // run the write as if it was addressed to the slave, the response is dropped
+            let len = request.len();
+            request[0] = id;
+            let crc = rtu::crc16(&request[..len - 2]).to_le_bytes();
+            request[len - 2..].copy_from_slice(&crc);
+            let mut response: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
+            if let Err(e) = process_request(id, request, ModbusProto::Rtu, slave.storage, slave.register_map, &mut response).await {
+                warn!("ModbusServer: broadcast to {} {}", id, Debug2Format(&e));
+            }
         }
-        if frame.response_required {
-            frame.finalize_response().unwrap();
-            trace!("ModbusServer: TX {}", Debug2Format(&response));
-            self.uart.write(response.as_slice()).await.map_err(|e| Error::Uart(e))?;
+    }
+}
+
+fn is_write_function(func: u8) -> bool {
+    matches!(func, 0x05 | 0x06 | 0x0F | 0x10 | 0x16)
+}
+
+/// PDU (function code and data) of an RTU or TCP ADU
+pub(crate) fn request_pdu(request: &[u8], proto: ModbusProto) -> &[u8] {
+    match proto {
+        ModbusProto::TcpUdp => request.get(TCP_PDU_OFFSET..).unwrap_or(&[]),
+        _ => request.get(1..request.len().saturating_sub(2)).unwrap_or(&[]),
+    }
+}
+
+fn request_unit_id(request: &[u8], proto: ModbusProto) -> Option<u8> {
+    match proto {
+        ModbusProto::TcpUdp => request.get(TCP_PDU_OFFSET - 1).copied(),
+        _ => request.first().copied(),
+    }
+}
+
+/// Wrap `pdu` into an RTU ADU
+pub(crate) fn rtu_response<const R: usize>(id: u8, pdu: &[u8], response: &mut Vec<u8, R>) -> Result<(), Error> {
+    response.clear();
+    response.push(id).map_err(|_| Error::ModbusProcess(rmodbus::ErrorKind::OOB))?;
+    response.extend_from_slice(pdu).map_err(|_| Error::ModbusProcess(rmodbus::ErrorKind::OOB))?;
+    let crc = rtu::crc16(response).to_le_bytes();
+    response.extend_from_slice(&crc).map_err(|_| Error::ModbusProcess(rmodbus::ErrorKind::OOB))
+}
+
+/// Wrap `pdu` into an ADU answering `request`
+fn adu_response<const R: usize>(request: &[u8], proto: ModbusProto, pdu: &[u8], response: &mut Vec<u8, R>) -> Result<(), Error> {
+    let unit_id = request_unit_id(request, proto).ok_or(Error::ModbusProcess(rmodbus::ErrorKind::FrameBroken))?;
+    match proto {
+        ModbusProto::TcpUdp => {
+            response.clear();
+            response.extend_from_slice(&request[..4])
+                .and_then(|_| response.extend_from_slice(&(pdu.len() as u16 + 1).to_be_bytes()))
+                .and_then(|_| response.push(unit_id).map_err(|_| ()))
+                .and_then(|_| response.extend_from_slice(pdu))
+                .map_err(|_| Error::ModbusProcess(rmodbus::ErrorKind::OOB))
+        },
+        _ => rtu_response(unit_id, pdu, response),
+    }
+}
+
+/// Build an exception answer to `request`
+pub(crate) fn exception_response<const R: usize>(request: &[u8], proto: ModbusProto, code: u8, response: &mut Vec<u8, R>) -> Result<(), Error> {
+    let func = *request_pdu(request, proto).first().ok_or(Error::ModbusProcess(rmodbus::ErrorKind::FrameBroken))?;
+    adu_response(request, proto, &[func | 0x80, code], response)
+}
+
+/// Run one request against `storage`, returns `true` if `response` has to be sent back.
+/// Writes rejected by `map` are answered with an exception and never reach the storage.
+pub(crate) async fn process_request<M: RawMutex, P: RegisterProvider, const R: usize>(
+    id: u8,
+    request: &[u8],
+    proto: ModbusProto,
+    storage: &Mutex<M, P>,
+    map: Option<&RegisterMap<'_>>,
+    response: &mut Vec<u8, R>,
+) -> Result<bool, Error> {
+    if request_unit_id(request, proto) != Some(id) {
+        return Ok(false);
+    }
+    let pdu = request_pdu(request, proto);
+    if pdu.is_empty() {
+        return Err(Error::ModbusProcess(rmodbus::ErrorKind::FrameBroken));
+    }
+    let mut storage_locked = storage.lock().await;
+    // the result of a mask write depends on the stored value, so it is checked under the same lock
+    let write = map.and_then(|map| {
+        WriteRequest::parse(pdu)
+            .or_else(|| WriteRequest::parse_mask_write(pdu, |reg| storage_locked.holding(reg).ok()))
+            .map(|w| (map, w))
+    });
+    if let Some((map, write)) = &write {
+        if let Err(e) = write.check(map) {
+            trace!("ModbusServer: write {} {} rejected {}", write.area, write.start, e);
+            exception_response(request, proto, e as u8, response)?;
+            return Ok(true);
         }
-        Ok(())
     }
-}
\ No newline at end of file
+
+    let mut answer: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
+    let res = pdu::process(pdu, &mut *storage_locked, &mut answer);
+    drop(storage_locked);
+    match res {
+        Ok(()) => {
+            if let Some((map, write)) = &write {
+                map.notify_write(write.area, write.start, write.count);
+            }
+            adu_response(request, proto, &answer, response)?;
+        },
+        Err(e) => {
+            trace!("ModbusServer: request failed {}", e);
+            exception_response(request, proto, e as u8, response)?;
+        },
+    }
+    Ok(true)
+}
@@ -0,0 +1,134 @@
+use heapless::Vec;
+
+pub const FC_ENCAPSULATED_INTERFACE: u8 = 0x2B;
+pub const MEI_READ_DEVICE_ID: u8 = 0x0E;
+
+/// Max PDU size of an answer
+const MAX_PDU_SIZE: usize = 253;
+/// Function, MEI type, read code, conformity level, more follows, next object id, number of objects
+const HEADER_SIZE: usize = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReadDeviceIdCode {
+    Basic = 0x01,
+    Regular = 0x02,
+    Extended = 0x03,
+    Specific = 0x04,
+}
+
+impl TryFrom<u8> for ReadDeviceIdCode {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x01 => Ok(ReadDeviceIdCode::Basic),
+            0x02 => Ok(ReadDeviceIdCode::Regular),
+            0x03 => Ok(ReadDeviceIdCode::Extended),
+            0x04 => Ok(ReadDeviceIdCode::Specific),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Static device description answered with FC 43 / MEI 14
+#[derive(Debug, Clone, Copy)]
+pub struct DeviceIdentification<'a> {
+    pub vendor_name: &'a str,
+    pub product_code: &'a str,
+    pub revision: &'a str,
+    pub vendor_url: Option<&'a str>,
+    pub product_name: Option<&'a str>,
+    pub model_name: Option<&'a str>,
+    pub user_application_name: Option<&'a str>,
+    /// Private objects, ids `0x80..=0xFF`
+    pub extended: &'a [(u8, &'a str)],
+}
+
+impl<'a> DeviceIdentification<'a> {
+    pub const fn new(vendor_name: &'a str, product_code: &'a str, revision: &'a str) -> Self {
+        Self {
+            vendor_name,
+            product_code,
+            revision,
+            vendor_url: None,
+            product_name: None,
+            model_name: None,
+            user_application_name: None,
+            extended: &[],
+        }
+    }
+
+    pub fn object(&self, id: u8) -> Option<&'a str> {
+        match id {
+            0x00 => Some(self.vendor_name),
+            0x01 => Some(self.product_code),
+            0x02 => Some(self.revision),
+            0x03 => self.vendor_url,
+            0x04 => self.product_name,
+            0x05 => self.model_name,
+            0x06 => self.user_application_name,
+            0x80..=0xFF => self.extended.iter().find(|(i, _)| *i == id).map(|(_, v)| *v),
+            _ => None,
+        }
+    }
+
+    fn conformity_level(&self) -> u8 {
+        // 0x80: individual access is supported
+        if !self.extended.is_empty() {
+            0x83
+        } else if (0x03..=0x06).any(|id| self.object(id).is_some()) {
+            0x82
+        } else {
+            0x81
+        }
+    }
+
+    /// Answer a read device identification request PDU. `Err` is the exception code.
+    pub fn process<const R: usize>(&self, pdu: &[u8], response: &mut Vec<u8, R>) -> Result<(), u8> {
+        if pdu.len() < 4 || pdu[0] != FC_ENCAPSULATED_INTERFACE || pdu[1] != MEI_READ_DEVICE_ID {
+            return Err(0x03);
+        }
+        let code = ReadDeviceIdCode::try_from(pdu[2]).map_err(|_| 0x03)?;
+        let mut object_id = pdu[3];
+        let last = match code {
+            ReadDeviceIdCode::Basic => 0x02,
+            ReadDeviceIdCode::Regular => 0x7F,
+            ReadDeviceIdCode::Extended | ReadDeviceIdCode::Specific => 0xFF,
+        };
+        if code == ReadDeviceIdCode::Specific {
+            if self.object(object_id).is_none() {
+                return Err(0x02);
+            }
+        } else if object_id > last || self.object(object_id).is_none() {
+            // unknown object restarts the stream from the beginning
+            object_id = 0x00;
+        }
+
+        response.clear();
+        response.extend_from_slice(&[FC_ENCAPSULATED_INTERFACE, MEI_READ_DEVICE_ID, code as u8, self.conformity_level(), 0x00, 0x00, 0x00])
+            .map_err(|_| 0x04)?;
+        let mut count = 0u8;
+        let mut id = object_id as u16;
+        while id <= last {
+            if let Some(value) = self.object(id as u8) {
+                let value = &value.as_bytes()[..value.len().min(MAX_PDU_SIZE - HEADER_SIZE - 2)];
+                if response.len() + 2 + value.len() > MAX_PDU_SIZE.min(R) {
+                    // does not fit, the client asks again starting from this object
+                    response[4] = 0xFF;
+                    response[5] = id as u8;
+                    break;
+                }
+                response.extend_from_slice(&[id as u8, value.len() as u8]).map_err(|_| 0x04)?;
+                response.extend_from_slice(value).map_err(|_| 0x04)?;
+                count += 1;
+            }
+            if code == ReadDeviceIdCode::Specific {
+                break;
+            }
+            id += 1;
+        }
+        response[6] = count;
+        Ok(())
+    }
+}
@@ -0,0 +1,295 @@
+use embassy_stm32::usart;
+use heapless::{Deque, Vec};
+
+use crate::components::server::modbus_link::FrameError;
+
+pub const FC_DIAGNOSTICS: u8 = 0x08;
+pub const FC_GET_COMM_EVENT_COUNTER: u8 = 0x0B;
+pub const FC_GET_COMM_EVENT_LOG: u8 = 0x0C;
+
+const EVENT_LOG_SIZE: usize = 64;
+
+const EVENT_RECEIVE: u8 = 0x80;
+const EVENT_RECEIVE_COMM_ERROR: u8 = 0x02;
+const EVENT_RECEIVE_OVERRUN: u8 = 0x10;
+const EVENT_RECEIVE_LISTEN_ONLY: u8 = 0x20;
+const EVENT_RECEIVE_BROADCAST: u8 = 0x40;
+const EVENT_SEND: u8 = 0x40;
+const EVENT_SEND_READ_EXCEPTION: u8 = 0x01;
+const EVENT_SEND_ABORT_EXCEPTION: u8 = 0x02;
+const EVENT_SEND_BUSY_EXCEPTION: u8 = 0x04;
+const EVENT_SEND_NAK_EXCEPTION: u8 = 0x08;
+const EVENT_SEND_LISTEN_ONLY: u8 = 0x20;
+const EVENT_LISTEN_ONLY: u8 = 0x04;
+const EVENT_RESTART: u8 = 0x00;
+
+/// FC 08 sub-functions
+mod sub {
+    pub const RETURN_QUERY_DATA: u16 = 0x00;
+    pub const RESTART_COMMUNICATIONS: u16 = 0x01;
+    pub const RETURN_DIAGNOSTIC_REGISTER: u16 = 0x02;
+    pub const FORCE_LISTEN_ONLY: u16 = 0x04;
+    pub const CLEAR_COUNTERS: u16 = 0x0A;
+    pub const BUS_MESSAGE_COUNT: u16 = 0x0B;
+    pub const BUS_COMM_ERROR_COUNT: u16 = 0x0C;
+    pub const BUS_EXCEPTION_ERROR_COUNT: u16 = 0x0D;
+    pub const SERVER_MESSAGE_COUNT: u16 = 0x0E;
+    pub const SERVER_NO_RESPONSE_COUNT: u16 = 0x0F;
+    pub const SERVER_NAK_COUNT: u16 = 0x10;
+    pub const SERVER_BUSY_COUNT: u16 = 0x11;
+    pub const BUS_CHAR_OVERRUN_COUNT: u16 = 0x12;
+    pub const CLEAR_OVERRUN_COUNTER: u16 = 0x14;
+}
+
+/// Serial line counters as defined for FC 08
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Counters {
+    pub bus_message: u16,
+    pub bus_comm_error: u16,
+    pub bus_exception: u16,
+    pub server_message: u16,
+    pub server_no_response: u16,
+    pub server_nak: u16,
+    pub server_busy: u16,
+    pub bus_char_overrun: u16,
+}
+
+/// Outcome of a diagnostics request
+pub enum DiagnosticsResponse {
+    /// Send the PDU back
+    Pdu,
+    NoResponse,
+    Exception(u8),
+}
+
+/// Statistics of a `ModbusServer`, answers FC 08, FC 11 and FC 12.
+#[derive(Default)]
+pub struct Diagnostics {
+    counters: Counters,
+    diagnostic_register: u16,
+    comm_event_counter: u16,
+    listen_only: bool,
+    /// most recent event first
+    events: Deque<u8, EVENT_LOG_SIZE>,
+}
+
+impl Diagnostics {
+    pub const fn new() -> Self {
+        Self {
+            counters: Counters {
+                bus_message: 0,
+                bus_comm_error: 0,
+                bus_exception: 0,
+                server_message: 0,
+                server_no_response: 0,
+                server_nak: 0,
+                server_busy: 0,
+                bus_char_overrun: 0,
+            },
+            diagnostic_register: 0,
+            comm_event_counter: 0,
+            listen_only: false,
+            events: Deque::new(),
+        }
+    }
+
+    pub fn counters(&self) -> &Counters {
+        &self.counters
+    }
+
+    pub fn comm_event_counter(&self) -> u16 {
+        self.comm_event_counter
+    }
+
+    pub fn listen_only(&self) -> bool {
+        self.listen_only
+    }
+
+    /// Reset the counters, the comm event counter and the diagnostic register, the event log is kept
+    pub fn clear(&mut self) {
+        self.counters = Counters::default();
+        self.diagnostic_register = 0;
+        self.comm_event_counter = 0;
+    }
+
+    fn log_event(&mut self, event: u8) {
+        if self.events.is_full() {
+            self.events.pop_back();
+        }
+        let _ = self.events.push_front(event);
+    }
+
+    /// Frame dropped by the link layer
+    pub fn on_frame_error(&mut self, error: &FrameError) {
+        match error {
+            FrameError::Crc | FrameError::Broken => {
+                self.counters.bus_message = self.counters.bus_message.wrapping_add(1);
+                self.counters.bus_comm_error = self.counters.bus_comm_error.wrapping_add(1);
+                self.log_event(EVENT_RECEIVE | EVENT_RECEIVE_COMM_ERROR);
+            },
+            FrameError::Overflow | FrameError::Uart(usart::Error::Overrun) => {
+                self.counters.bus_char_overrun = self.counters.bus_char_overrun.wrapping_add(1);
+                self.log_event(EVENT_RECEIVE | EVENT_RECEIVE_OVERRUN);
+            },
+            _ => {},
+        }
+    }
+
+    /// Valid frame seen on the bus, `addressed` if it is for this server or a broadcast
+    pub fn on_message(&mut self, addressed: bool, broadcast: bool) {
+        self.counters.bus_message = self.counters.bus_message.wrapping_add(1);
+        if !addressed && !broadcast {
+            return;
+        }
+        self.counters.server_message = self.counters.server_message.wrapping_add(1);
+        let mut event = EVENT_RECEIVE;
+        if broadcast {
+            event |= EVENT_RECEIVE_BROADCAST;
+            self.counters.server_no_response = self.counters.server_no_response.wrapping_add(1);
+        }
+        if self.listen_only {
+            event |= EVENT_RECEIVE_LISTEN_ONLY;
+        }
+        self.log_event(event);
+    }
+
+    /// Answer sent back for function `func`, `exception` is the exception code if any
+    pub fn on_response(&mut self, func: u8, exception: Option<u8>) {
+        let mut event = EVENT_SEND;
+        match exception {
+            Some(code) => {
+                self.counters.bus_exception = self.counters.bus_exception.wrapping_add(1);
+                event |= match code {
+                    0x01..=0x03 => EVENT_SEND_READ_EXCEPTION,
+                    0x04 => EVENT_SEND_ABORT_EXCEPTION,
+                    0x05 | 0x06 => {
+                        self.counters.server_busy = self.counters.server_busy.wrapping_add(1);
+                        EVENT_SEND_BUSY_EXCEPTION
+                    },
+                    _ => {
+                        self.counters.server_nak = self.counters.server_nak.wrapping_add(1);
+                        EVENT_SEND_NAK_EXCEPTION
+                    },
+                };
+            },
+            None => {
+                // polls and event counter fetches are not counted
+                if func != FC_GET_COMM_EVENT_COUNTER && func != FC_GET_COMM_EVENT_LOG {
+                    self.comm_event_counter = self.comm_event_counter.wrapping_add(1);
+                }
+            },
+        }
+        if self.listen_only {
+            event |= EVENT_SEND_LISTEN_ONLY;
+        }
+        self.log_event(event);
+    }
+
+    /// No answer for a request addressed to this server
+    pub fn on_no_response(&mut self) {
+        self.counters.server_no_response = self.counters.server_no_response.wrapping_add(1);
+    }
+
+    pub fn is_diagnostics_function(func: u8) -> bool {
+        matches!(func, FC_DIAGNOSTICS | FC_GET_COMM_EVENT_COUNTER | FC_GET_COMM_EVENT_LOG)
+    }
+
+    /// Restart Communications Option (FC 08, sub-function 0x0001), the only request processed in listen only mode
+    pub fn is_restart_request(pdu: &[u8]) -> bool {
+        pdu.len() >= 3 && pdu[0] == FC_DIAGNOSTICS && u16::from_be_bytes([pdu[1], pdu[2]]) == sub::RESTART_COMMUNICATIONS
+    }
+
+    /// Process a request PDU for one of the diagnostics functions, the answer PDU is put into `response`
+    pub fn process<const R: usize>(&mut self, pdu: &[u8], response: &mut Vec<u8, R>) -> DiagnosticsResponse {
+        response.clear();
+        let res = match pdu.first() {
+            Some(&FC_DIAGNOSTICS) => self.diagnostics(pdu, response),
+            Some(&FC_GET_COMM_EVENT_COUNTER) => self.event_counter(response),
+            Some(&FC_GET_COMM_EVENT_LOG) => self.event_log(response),
+            _ => return DiagnosticsResponse::Exception(0x01),
+        };
+        match res {
+            Ok(res) => res,
+            // answer does not fit into the response buffer
+            Err(()) => DiagnosticsResponse::Exception(0x04),
+        }
+    }
+
+    fn diagnostics<const R: usize>(&mut self, pdu: &[u8], response: &mut Vec<u8, R>) -> Result<DiagnosticsResponse, ()> {
+        if pdu.len() < 5 {
+            return Ok(DiagnosticsResponse::Exception(0x03));
+        }
+        let sub_function = u16::from_be_bytes([pdu[1], pdu[2]]);
+        let data = u16::from_be_bytes([pdu[3], pdu[4]]);
+        let value = match sub_function {
+            sub::RETURN_QUERY_DATA => {
+                response.extend_from_slice(pdu).map_err(|_| ())?;
+                return Ok(DiagnosticsResponse::Pdu);
+            },
+            sub::RESTART_COMMUNICATIONS => {
+                if data != 0x0000 && data != 0xFF00 {
+                    return Ok(DiagnosticsResponse::Exception(0x03));
+                }
+                self.clear();
+                if data == 0xFF00 {
+                    self.events.clear();
+                }
+                self.log_event(EVENT_RESTART);
+                let was_listen_only = self.listen_only;
+                self.listen_only = false;
+                if was_listen_only {
+                    // a restart request in listen only mode is not answered
+                    return Ok(DiagnosticsResponse::NoResponse);
+                }
+                None
+            },
+            sub::RETURN_DIAGNOSTIC_REGISTER => Some(self.diagnostic_register),
+            sub::FORCE_LISTEN_ONLY => {
+                self.listen_only = true;
+                self.log_event(EVENT_LISTEN_ONLY);
+                return Ok(DiagnosticsResponse::NoResponse);
+            },
+            sub::CLEAR_COUNTERS => {
+                self.clear();
+                None
+            },
+            sub::BUS_MESSAGE_COUNT => Some(self.counters.bus_message),
+            sub::BUS_COMM_ERROR_COUNT => Some(self.counters.bus_comm_error),
+            sub::BUS_EXCEPTION_ERROR_COUNT => Some(self.counters.bus_exception),
+            sub::SERVER_MESSAGE_COUNT => Some(self.counters.server_message),
+            sub::SERVER_NO_RESPONSE_COUNT => Some(self.counters.server_no_response),
+            sub::SERVER_NAK_COUNT => Some(self.counters.server_nak),
+            sub::SERVER_BUSY_COUNT => Some(self.counters.server_busy),
+            sub::BUS_CHAR_OVERRUN_COUNT => Some(self.counters.bus_char_overrun),
+            sub::CLEAR_OVERRUN_COUNTER => {
+                self.counters.bus_char_overrun = 0;
+                None
+            },
+            _ => return Ok(DiagnosticsResponse::Exception(0x01)),
+        };
+        response.extend_from_slice(&pdu[..3]).map_err(|_| ())?;
+        // sub-functions without a value echo the request data
+        response.extend_from_slice(&value.unwrap_or(data).to_be_bytes()).map_err(|_| ())?;
+        Ok(DiagnosticsResponse::Pdu)
+    }
+
+    fn event_counter<const R: usize>(&self, response: &mut Vec<u8, R>) -> Result<DiagnosticsResponse, ()> {
+        response.push(FC_GET_COMM_EVENT_COUNTER).map_err(|_| ())?;
+        response.extend_from_slice(&0u16.to_be_bytes()).map_err(|_| ())?;
+        response.extend_from_slice(&self.comm_event_counter.to_be_bytes()).map_err(|_| ())?;
+        Ok(DiagnosticsResponse::Pdu)
+    }
+
+    fn event_log<const R: usize>(&self, response: &mut Vec<u8, R>) -> Result<DiagnosticsResponse, ()> {
+        response.push(FC_GET_COMM_EVENT_LOG).map_err(|_| ())?;
+        response.push(6 + self.events.len() as u8).map_err(|_| ())?;
+        response.extend_from_slice(&0u16.to_be_bytes()).map_err(|_| ())?;
+        response.extend_from_slice(&self.comm_event_counter.to_be_bytes()).map_err(|_| ())?;
+        response.extend_from_slice(&self.counters.bus_message.to_be_bytes()).map_err(|_| ())?;
+        for event in self.events.iter() {
+            response.push(*event).map_err(|_| ())?;
+        }
+        Ok(DiagnosticsResponse::Pdu)
+    }
+}
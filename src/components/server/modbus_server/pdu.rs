@@ -0,0 +1,130 @@
+//! Request processing of the data access functions (FC 1-6, 15, 16 and 22) against a `RegisterProvider`
+
+use heapless::Vec;
+
+use crate::components::server::register_map::{Area, Exception};
+use crate::components::server::register_provider::RegisterProvider;
+
+const MAX_READ_BITS: u16 = 2000;
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_BITS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+
+fn push<const R: usize>(response: &mut Vec<u8, R>, data: &[u8]) -> Result<(), Exception> {
+    response.extend_from_slice(data).map_err(|_| Exception::ServerDeviceFailure)
+}
+
+/// `start` and `count` of a request, checked against `max`
+fn range(pdu: &[u8], max: u16) -> Result<(u16, u16), Exception> {
+    if pdu.len() < 5 {
+        return Err(Exception::IllegalDataValue);
+    }
+    let start = u16::from_be_bytes([pdu[1], pdu[2]]);
+    let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+    if count == 0 || count > max {
+        return Err(Exception::IllegalDataValue);
+    }
+    if start.checked_add(count - 1).is_none() {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok((start, count))
+}
+
+fn read_bits<const R: usize>(pdu: &[u8], response: &mut Vec<u8, R>, get: impl Fn(u16) -> Result<bool, Exception>) -> Result<(), Exception> {
+    let (start, count) = range(pdu, MAX_READ_BITS)?;
+    let bytes = count.div_ceil(8) as usize;
+    push(response, &[pdu[0], bytes as u8])?;
+    for byte in 0..bytes {
+        let mut value = 0u8;
+        for bit in 0..8 {
+            let i = (byte * 8 + bit) as u16;
+            if i < count && get(start + i)? {
+                value |= 1 << bit;
+            }
+        }
+        push(response, &[value])?;
+    }
+    Ok(())
+}
+
+fn read_registers<const R: usize>(pdu: &[u8], response: &mut Vec<u8, R>, get: impl Fn(u16) -> Result<u16, Exception>) -> Result<(), Exception> {
+    let (start, count) = range(pdu, MAX_READ_REGISTERS)?;
+    push(response, &[pdu[0], (count * 2) as u8])?;
+    for reg in start..=start + (count - 1) {
+        push(response, &get(reg)?.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Payload of FC 15/16 after checking its byte count
+fn write_data(pdu: &[u8], bytes: usize) -> Result<&[u8], Exception> {
+    match pdu.get(5) {
+        Some(&n) if n as usize == bytes => pdu.get(6..6 + bytes).ok_or(Exception::IllegalDataValue),
+        _ => Err(Exception::IllegalDataValue),
+    }
+}
+
+/// Result of Mask Write Register (FC 22) on the `current` register value
+pub fn mask_write(current: u16, and_mask: u16, or_mask: u16) -> u16 {
+    (current & and_mask) | (or_mask & !and_mask)
+}
+
+/// Process a request PDU (function code and data), the answer PDU is put into `response`
+pub fn process<P: RegisterProvider + ?Sized, const R: usize>(pdu: &[u8], provider: &mut P, response: &mut Vec<u8, R>) -> Result<(), Exception> {
+    response.clear();
+    let func = *pdu.first().ok_or(Exception::IllegalFunction)?;
+    match func {
+        0x01 => read_bits(pdu, response, |reg| provider.coil(reg)),
+        0x02 => read_bits(pdu, response, |reg| provider.discrete(reg)),
+        0x03 => read_registers(pdu, response, |reg| provider.holding(reg)),
+        0x04 => read_registers(pdu, response, |reg| provider.input(reg)),
+        0x05 => {
+            if pdu.len() < 5 {
+                return Err(Exception::IllegalDataValue);
+            }
+            let value = match [pdu[3], pdu[4]] {
+                [0xFF, 0x00] => true,
+                [0x00, 0x00] => false,
+                _ => return Err(Exception::IllegalDataValue),
+            };
+            provider.set_coil(u16::from_be_bytes([pdu[1], pdu[2]]), value)?;
+            push(response, &pdu[..5])
+        },
+        0x06 => {
+            if pdu.len() < 5 {
+                return Err(Exception::IllegalDataValue);
+            }
+            provider.set_holding(u16::from_be_bytes([pdu[1], pdu[2]]), u16::from_be_bytes([pdu[3], pdu[4]]))?;
+            push(response, &pdu[..5])
+        },
+        0x0F => {
+            let (start, count) = range(pdu, MAX_WRITE_BITS)?;
+            let data = write_data(pdu, count.div_ceil(8) as usize)?;
+            provider.check_writable(Area::Coil, start, count)?;
+            for i in 0..count {
+                let bit = data[i as usize / 8] >> (i % 8) & 1;
+                provider.set_coil(start + i, bit == 1)?;
+            }
+            push(response, &pdu[..5])
+        },
+        0x10 => {
+            let (start, count) = range(pdu, MAX_WRITE_REGISTERS)?;
+            let data = write_data(pdu, count as usize * 2)?;
+            provider.check_writable(Area::Holding, start, count)?;
+            for (i, value) in data.chunks_exact(2).enumerate() {
+                provider.set_holding(start + i as u16, u16::from_be_bytes([value[0], value[1]]))?;
+            }
+            push(response, &pdu[..5])
+        },
+        0x16 => {
+            if pdu.len() < 7 {
+                return Err(Exception::IllegalDataValue);
+            }
+            let reg = u16::from_be_bytes([pdu[1], pdu[2]]);
+            let value = mask_write(provider.holding(reg)?, u16::from_be_bytes([pdu[3], pdu[4]]), u16::from_be_bytes([pdu[5], pdu[6]]));
+            provider.set_holding(reg, value)?;
+            push(response, &pdu[..7])
+        },
+        _ => Err(Exception::IllegalFunction),
+    }
+}
@@ -0,0 +1,93 @@
+use embassy_stm32::usart;
+
+use super::Error;
+use crate::components::server::modbus_link::FrameError;
+
+/// Number of input registers written by `ServerStats::to_registers`
+pub const STATS_REGISTERS: usize = 10;
+
+/// Outcome of every frame seen by a `ModbusServer`, all counters wrap around
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ServerStats {
+    /// Requests addressed to one of the served slaves
+    pub requests: u16,
+    pub responses: u16,
+    /// Responses carrying an exception code
+    pub exceptions: u16,
+    pub broadcasts: u16,
+    /// Valid frames for other slaves
+    pub ignored: u16,
+    pub crc_errors: u16,
+    /// Broken or oversized frames
+    pub frame_errors: u16,
+    /// UART framing, noise and parity errors
+    pub uart_errors: u16,
+    pub overruns: u16,
+    /// Requests dropped because they could not be processed or answered
+    pub process_errors: u16,
+}
+
+impl ServerStats {
+    pub const fn new() -> Self {
+        Self {
+            requests: 0,
+            responses: 0,
+            exceptions: 0,
+            broadcasts: 0,
+            ignored: 0,
+            crc_errors: 0,
+            frame_errors: 0,
+            uart_errors: 0,
+            overruns: 0,
+            process_errors: 0,
+        }
+    }
+
+    pub fn on_error(&mut self, error: &Error) {
+        let counter = match error {
+            Error::Frame(FrameError::Crc) => &mut self.crc_errors,
+            Error::Frame(FrameError::Broken | FrameError::Overflow) => &mut self.frame_errors,
+            Error::Frame(FrameError::Uart(usart::Error::Overrun)) | Error::Uart(usart::Error::Overrun) => &mut self.overruns,
+            Error::Frame(FrameError::Uart(_)) | Error::Uart(_) => &mut self.uart_errors,
+            Error::Frame(FrameError::Timeout) => return,
+            Error::ModbusProcess(_) => &mut self.process_errors,
+        };
+        *counter = counter.wrapping_add(1);
+    }
+
+    pub(crate) fn on_request(&mut self) {
+        self.requests = self.requests.wrapping_add(1);
+    }
+
+    pub(crate) fn on_broadcast(&mut self) {
+        self.broadcasts = self.broadcasts.wrapping_add(1);
+    }
+
+    pub(crate) fn on_ignored(&mut self) {
+        self.ignored = self.ignored.wrapping_add(1);
+    }
+
+    pub(crate) fn on_response(&mut self, exception: bool) {
+        self.responses = self.responses.wrapping_add(1);
+        if exception {
+            self.exceptions = self.exceptions.wrapping_add(1);
+        }
+    }
+
+    /// Counters in declaration order, e.g. to mirror them into input registers
+    pub fn to_registers(&self) -> [u16; STATS_REGISTERS] {
+        [
+            self.requests,
+            self.responses,
+            self.exceptions,
+            self.broadcasts,
+            self.ignored,
+            self.crc_errors,
+            self.frame_errors,
+            self.uart_errors,
+            self.overruns,
+            self.process_errors,
+        ]
+    }
+}
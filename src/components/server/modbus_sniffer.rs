@@ -0,0 +1,158 @@
+use defmt::{trace, warn};
+use embassy_sync::channel::DynamicSender;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use rmodbus::{server::ModbusFrame, ModbusFrameBuf, ModbusProto};
+
+use crate::components::com::transport::Transport;
+use super::modbus_link::{rtu::{self, RtuTiming}, FrameError};
+
+const MODBUS_BUF_SIZE: usize = 256;
+const BROADCAST_ID: u8 = 0;
+
+/// Request seen on the bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RequestInfo {
+    pub unit_id: u8,
+    pub func: u8,
+    /// First register or coil, 0 for functions without an address
+    pub start: u16,
+    pub count: u16,
+    /// End of the request frame
+    pub at: Instant,
+    /// Bus silence before the request
+    pub gap: Duration,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BusEvent {
+    /// Request answered by its slave, `exception` is the exception code if any
+    Transaction { request: RequestInfo, exception: Option<u8>, response_time: Duration },
+    NoResponse(RequestInfo),
+    Broadcast(RequestInfo),
+    /// Frame that neither is a valid request nor matches the pending one
+    Unmatched { unit_id: u8, func: u8, len: usize },
+    /// Frame dropped because of a bad CRC, bad framing or a UART error
+    FrameError(FrameError),
+}
+
+/// Passive Modbus RTU monitor, never transmits. Frames are paired into request and response,
+/// decoded and sent as `BusEvent`. Events are dropped while the channel is full.
+pub struct ModbusSniffer<'a, T: Transport> {
+    port: T,
+    timing: RtuTiming,
+    response_timeout: Duration,
+    events: DynamicSender<'a, BusEvent>,
+    pending: Option<RequestInfo>,
+    last_frame_end: Option<Instant>,
+    dropped: u32,
+}
+
+impl<'a, T: Transport> ModbusSniffer<'a, T> {
+    pub fn new(port: T, baudrate: u32, events: DynamicSender<'a, BusEvent>) -> Self {
+        Self::new_advanced(port, RtuTiming::from_baudrate(baudrate), Duration::from_millis(1000), events)
+    }
+
+    /// A frame arriving later than `response_timeout` after a request is taken as the next request
+    pub fn new_advanced(port: T, timing: RtuTiming, response_timeout: Duration, events: DynamicSender<'a, BusEvent>) -> Self {
+        Self {
+            port,
+            timing,
+            response_timeout,
+            events,
+            pending: None,
+            last_frame_end: None,
+            dropped: 0,
+        }
+    }
+
+    /// Events lost because the channel was full
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.update().await;
+        }
+    }
+
+    /// Wait for the next frame and decode it. A pending request is reported as `NoResponse`
+    /// once the response timeout passed without any frame on the bus.
+    pub async fn update(&mut self) {
+        let mut buf: ModbusFrameBuf = [0; MODBUS_BUF_SIZE];
+        let res = match &self.pending {
+            Some(request) => {
+                let timeout = (request.at + self.response_timeout).saturating_duration_since(Instant::now());
+                match rtu::read_response(&mut self.port, &self.timing, &mut buf, timeout).await {
+                    Err(FrameError::Timeout) => {
+                        if let Some(request) = self.pending.take() {
+                            self.emit(BusEvent::NoResponse(request));
+                        }
+                        return;
+                    },
+                    res => res,
+                }
+            },
+            None => rtu::read_frame(&mut self.port, &self.timing, &mut buf).await,
+        };
+        // the frame ended one t3.5 silence before the read returned
+        let now = Instant::now();
+        let end = now.checked_sub(self.timing.char_time + self.timing.t35).unwrap_or(now);
+        let len = match res {
+            Ok(len) => len,
+            Err(e) => {
+                self.last_frame_end = Some(end);
+                self.emit(BusEvent::FrameError(e));
+                return;
+            },
+        };
+        let frame = &buf[..len];
+        trace!("ModbusSniffer: {}", frame);
+        let start = end.checked_sub(self.timing.char_time * len as u32).unwrap_or(end);
+        let gap = self.last_frame_end.map_or(Duration::from_ticks(0), |last| start.saturating_duration_since(last));
+        self.last_frame_end = Some(end);
+
+        if let Some(request) = self.pending.take() {
+            if end - request.at <= self.response_timeout + self.timing.char_time * len as u32 && is_response_to(&request, frame) {
+                let exception = (frame[1] & 0x80 != 0).then(|| frame[2]);
+                self.emit(BusEvent::Transaction { request, exception, response_time: start.saturating_duration_since(request.at) });
+                return;
+            }
+            self.emit(BusEvent::NoResponse(request));
+        }
+
+        match parse_request(frame, end, gap) {
+            Some(request) if request.unit_id == BROADCAST_ID => self.emit(BusEvent::Broadcast(request)),
+            Some(request) => self.pending = Some(request),
+            None => self.emit(BusEvent::Unmatched { unit_id: frame[0], func: frame[1], len }),
+        }
+    }
+
+    fn emit(&mut self, event: BusEvent) {
+        if self.events.try_send(event).is_err() {
+            self.dropped = self.dropped.wrapping_add(1);
+            warn!("ModbusSniffer: event dropped");
+        }
+    }
+}
+
+/// Same slave and function, exception answers have the high bit of the function set
+fn is_response_to(request: &RequestInfo, frame: &[u8]) -> bool {
+    frame[0] == request.unit_id && frame[1] & 0x7F == request.func
+}
+
+/// Decode a request with the rmodbus server parser
+fn parse_request(frame: &[u8], at: Instant, gap: Duration) -> Option<RequestInfo> {
+    let unit_id = frame[0];
+    let mut response: Vec<u8, MODBUS_BUF_SIZE> = Vec::new();
+    let mut parsed = ModbusFrame::new(unit_id, frame, ModbusProto::Rtu, &mut response);
+    parsed.parse().ok()?;
+    if frame[1] & 0x80 != 0 {
+        return None;
+    }
+    let (start, count) = if parsed.processing_required { (parsed.reg, parsed.count) } else { (0, 0) };
+    Some(RequestInfo { unit_id, func: parsed.func, start, count, at, gap })
+}
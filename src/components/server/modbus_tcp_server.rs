@@ -0,0 +1,156 @@
+use defmt::{info, trace, warn, Debug2Format};
+use embassy_futures::join::join_array;
+use embassy_net::{tcp::{self, TcpSocket}, Stack};
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+use embassy_time::{with_timeout, Duration, Timer};
+use heapless::Vec;
+use rmodbus::ModbusProto;
+
+use super::modbus_server::{self, process_request};
+use super::register_map::RegisterMap;
+use super::register_provider::RegisterProvider;
+
+pub const MODBUS_TCP_PORT: u16 = 502;
+
+/// Transaction id, protocol id, length and unit id
+const MBAP_HEADER_SIZE: usize = 7;
+const MAX_ADU_SIZE: usize = 260;
+const SOCKET_BUF_SIZE: usize = 512;
+/// Pause after a failed `accept` so a persistent error does not spin the task
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum Error {
+    Tcp(tcp::Error),
+    /// Client did not send anything within the idle timeout
+    IdleTimeout,
+    ConnectionClosed,
+    /// MBAP header with a wrong protocol id or length
+    InvalidHeader,
+    Process(modbus_server::Error),
+}
+
+/// Socket buffers of one client connection
+pub struct TcpBuffers {
+    rx: [u8; SOCKET_BUF_SIZE],
+    tx: [u8; SOCKET_BUF_SIZE],
+}
+
+impl TcpBuffers {
+    pub const fn new() -> Self {
+        Self { rx: [0; SOCKET_BUF_SIZE], tx: [0; SOCKET_BUF_SIZE] }
+    }
+}
+
+impl Default for TcpBuffers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Modbus TCP counterpart of `ModbusServer`, serves the same shared storage.
+pub struct ModbusTcpServer<'a, M: RawMutex, P: RegisterProvider> {
+    storage: &'a Mutex<M, P>,
+    unit_id: u8,
+    port: u16,
+    idle_timeout: Duration,
+    register_map: Option<&'a RegisterMap<'a>>,
+}
+
+impl<'a, M: RawMutex, P: RegisterProvider> ModbusTcpServer<'a, M, P> {
+    pub fn new(storage: &'a Mutex<M, P>, unit_id: u8) -> Self {
+        Self::new_advanced(storage, unit_id, MODBUS_TCP_PORT, Duration::from_secs(60))
+    }
+
+    pub fn new_advanced(storage: &'a Mutex<M, P>, unit_id: u8, port: u16, idle_timeout: Duration) -> Self {
+        Self {
+            storage,
+            unit_id,
+            port,
+            idle_timeout,
+            register_map: None,
+        }
+    }
+
+    /// Check every write against `register_map` before it reaches the storage
+    pub fn set_register_map(&mut self, register_map: &'a RegisterMap<'a>) {
+        self.register_map = Some(register_map);
+    }
+
+    /// Serve up to `N` clients at once, one connection per `TcpBuffers`. Never returns.
+    pub async fn run<const N: usize>(&self, stack: Stack<'_>, buffers: &mut [TcpBuffers; N]) -> ! {
+        let mut buffers = buffers.iter_mut();
+        let handlers: [_; N] = core::array::from_fn(|_| self.serve_socket(stack, buffers.next().unwrap()));
+        join_array(handlers).await;
+        unreachable!()
+    }
+
+    async fn serve_socket(&self, stack: Stack<'_>, buffers: &mut TcpBuffers) -> ! {
+        loop {
+            let mut socket = TcpSocket::new(stack, &mut buffers.rx, &mut buffers.tx);
+            if let Err(e) = socket.accept(self.port).await {
+                warn!("ModbusTcpServer: accept {}", Debug2Format(&e));
+                Timer::after(ACCEPT_RETRY_DELAY).await;
+                continue;
+            }
+            info!("ModbusTcpServer: connected {}", Debug2Format(&socket.remote_endpoint()));
+            if let Err(e) = self.serve_client(&mut socket).await {
+                info!("ModbusTcpServer: closed {}", Debug2Format(&e));
+            }
+            socket.close();
+            let _ = with_timeout(Duration::from_secs(1), socket.flush()).await;
+            socket.abort();
+        }
+    }
+
+    async fn serve_client(&self, socket: &mut TcpSocket<'_>) -> Result<(), Error> {
+        loop {
+            let mut request = [0u8; MAX_ADU_SIZE];
+            with_timeout(self.idle_timeout, read_exact(socket, &mut request[..MBAP_HEADER_SIZE])).await
+                .map_err(|_| Error::IdleTimeout)??;
+            if request[2..4] != [0, 0] {
+                return Err(Error::InvalidHeader);
+            }
+            // length counts the unit id, which is already read with the header
+            let length = u16::from_be_bytes([request[4], request[5]]) as usize;
+            if length < 2 || MBAP_HEADER_SIZE - 1 + length > MAX_ADU_SIZE {
+                return Err(Error::InvalidHeader);
+            }
+            let count = MBAP_HEADER_SIZE - 1 + length;
+            with_timeout(self.idle_timeout, read_exact(socket, &mut request[MBAP_HEADER_SIZE..count])).await
+                .map_err(|_| Error::IdleTimeout)??;
+            trace!("ModbusTcpServer: RX {}", &request[..count]);
+
+            let mut response: Vec<u8, MAX_ADU_SIZE> = Vec::new();
+            match process_request(self.unit_id, &request[..count], ModbusProto::TcpUdp, self.storage, self.register_map, &mut response).await {
+                Ok(true) => {
+                    trace!("ModbusTcpServer: TX {}", Debug2Format(&response));
+                    write_all(socket, &response).await?;
+                },
+                Ok(false) => {},
+                // a bad request does not break the connection
+                Err(e) => warn!("ModbusTcpServer: {}", Debug2Format(&Error::Process(e))),
+            }
+        }
+    }
+}
+
+async fn read_exact(socket: &mut TcpSocket<'_>, mut buf: &mut [u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match socket.read(buf).await.map_err(|e| Error::Tcp(e))? {
+            0 => return Err(Error::ConnectionClosed),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
+
+async fn write_all(socket: &mut TcpSocket<'_>, mut buf: &[u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match socket.write(buf).await.map_err(|e| Error::Tcp(e))? {
+            0 => return Err(Error::ConnectionClosed),
+            n => buf = &buf[n..],
+        }
+    }
+    Ok(())
+}
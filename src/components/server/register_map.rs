@@ -0,0 +1,187 @@
+use defmt::warn;
+use embassy_sync::channel::DynamicSender;
+pub use def::{Access, Area, Validation};
+use def::Register;
+
+use super::modbus_server::pdu::mask_write;
+
+pub mod def;
+pub mod export;
+pub mod typed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegisterRange {
+    pub area: Area,
+    pub start: u16,
+    pub count: u16,
+    pub access: Access,
+    pub validation: Validation,
+}
+
+impl RegisterRange {
+    pub const fn read_only(area: Area, start: u16, count: u16) -> Self {
+        Self { area, start, count, access: Access::ReadOnly, validation: Validation::None }
+    }
+
+    pub const fn read_write(area: Area, start: u16, count: u16) -> Self {
+        Self { area, start, count, access: Access::ReadWrite, validation: Validation::None }
+    }
+
+    pub const fn validated(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub fn contains(&self, area: Area, reg: u16) -> bool {
+        self.area == area && reg >= self.start && (reg - self.start) < self.count
+    }
+}
+
+/// Modbus exception codes returned for rejected requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Exception {
+    IllegalFunction = 0x01,
+    IllegalDataAddress = 0x02,
+    IllegalDataValue = 0x03,
+    ServerDeviceFailure = 0x04,
+}
+
+/// Registers changed by an accepted write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct WriteEvent {
+    pub area: Area,
+    pub start: u16,
+    pub count: u16,
+}
+
+/// Access rights and value checks for the shared storage. Registers not covered by any
+/// range or typed register stay read-write without validation.
+pub struct RegisterMap<'a> {
+    ranges: &'a [RegisterRange],
+    registers: &'a [Register],
+    notify: Option<DynamicSender<'a, WriteEvent>>,
+}
+
+impl<'a> RegisterMap<'a> {
+    pub const fn new(ranges: &'a [RegisterRange]) -> Self {
+        Self { ranges, registers: &[], notify: None }
+    }
+
+    /// Every accepted write is reported into `sender`, events are dropped while it is full
+    pub fn new_with_notify(ranges: &'a [RegisterRange], sender: DynamicSender<'a, WriteEvent>) -> Self {
+        Self { ranges, registers: &[], notify: Some(sender) }
+    }
+
+    /// Check writes against typed register definitions as well
+    pub const fn with_registers(mut self, registers: &'a [Register]) -> Self {
+        self.registers = registers;
+        self
+    }
+
+    pub fn registers(&self) -> &'a [Register] {
+        self.registers
+    }
+
+    pub fn find(&self, area: Area, reg: u16) -> Option<&RegisterRange> {
+        self.ranges.iter().find(|r| r.contains(area, reg))
+    }
+
+    pub fn find_register(&self, area: Area, reg: u16) -> Option<&Register> {
+        self.registers.iter().find(|r| r.contains(area, reg))
+    }
+
+    /// Check a write of `values` starting at `start`
+    pub fn check_write(&self, area: Area, start: u16, values: impl Iterator<Item = u16>) -> Result<(), Exception> {
+        for (i, value) in values.enumerate() {
+            let reg = start.checked_add(i as u16).ok_or(Exception::IllegalDataAddress)?;
+            if let Some(range) = self.find(area, reg) {
+                if range.access == Access::ReadOnly {
+                    return Err(Exception::IllegalDataAddress);
+                }
+                if !range.validation.is_valid(value) {
+                    return Err(Exception::IllegalDataValue);
+                }
+            }
+            if let Some(register) = self.find_register(area, reg) {
+                if register.access == Access::ReadOnly {
+                    return Err(Exception::IllegalDataAddress);
+                }
+                if register.words() == 1 && !register.validation.is_valid(value) {
+                    return Err(Exception::IllegalDataValue);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn notify_write(&self, area: Area, start: u16, count: u16) {
+        if let Some(sender) = &self.notify {
+            if sender.try_send(WriteEvent { area, start, count }).is_err() {
+                warn!("RegisterMap: write event dropped");
+            }
+        }
+    }
+}
+
+/// Write request decoded from a Modbus PDU (function code and data)
+pub(crate) struct WriteRequest<'a> {
+    pub area: Area,
+    pub start: u16,
+    pub count: u16,
+    values: WriteValues<'a>,
+}
+
+enum WriteValues<'a> {
+    Single(u16),
+    Coils(&'a [u8]),
+    Registers(&'a [u8]),
+}
+
+impl<'a> WriteRequest<'a> {
+    /// `None` for anything that is not a well formed write
+    pub fn parse(pdu: &'a [u8]) -> Option<Self> {
+        if pdu.len() < 5 {
+            return None;
+        }
+        let start = u16::from_be_bytes([pdu[1], pdu[2]]);
+        let value = u16::from_be_bytes([pdu[3], pdu[4]]);
+        match pdu[0] {
+            0x05 => Some(Self { area: Area::Coil, start, count: 1, values: WriteValues::Single((value == 0xFF00) as u16) }),
+            0x06 => Some(Self { area: Area::Holding, start, count: 1, values: WriteValues::Single(value) }),
+            0x0F => {
+                let bytes = pdu.get(6..6 + *pdu.get(5)? as usize)?;
+                Some(Self { area: Area::Coil, start, count: value, values: WriteValues::Coils(bytes) })
+            },
+            0x10 => {
+                let bytes = pdu.get(6..6 + *pdu.get(5)? as usize)?;
+                Some(Self { area: Area::Holding, start, count: value, values: WriteValues::Registers(bytes) })
+            },
+            _ => None,
+        }
+    }
+
+    /// Mask Write Register (FC 22) is checked as the single register write of its result,
+    /// `holding` returns the stored value of the register
+    pub fn parse_mask_write(pdu: &'a [u8], holding: impl FnOnce(u16) -> Option<u16>) -> Option<Self> {
+        if pdu.len() < 7 || pdu[0] != 0x16 {
+            return None;
+        }
+        let start = u16::from_be_bytes([pdu[1], pdu[2]]);
+        let value = mask_write(holding(start)?, u16::from_be_bytes([pdu[3], pdu[4]]), u16::from_be_bytes([pdu[5], pdu[6]]));
+        Some(Self { area: Area::Holding, start, count: 1, values: WriteValues::Single(value) })
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.count as usize).map_while(move |i| match self.values {
+            WriteValues::Single(v) => Some(v),
+            WriteValues::Coils(bytes) => bytes.get(i / 8).map(|b| ((b >> (i % 8)) & 1) as u16),
+            WriteValues::Registers(bytes) => bytes.get(i * 2..i * 2 + 2).map(|b| u16::from_be_bytes([b[0], b[1]])),
+        })
+    }
+
+    pub fn check(&self, map: &RegisterMap) -> Result<(), Exception> {
+        map.check_write(self.area, self.start, self.values())
+    }
+}
@@ -0,0 +1,130 @@
+//! Register descriptions. Only depends on `core`, the host tests in `host-tests` build it and `export` for std.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Area {
+    Coil,
+    Discrete,
+    Input,
+    Holding,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Access {
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    None,
+    Range { min: u16, max: u16 },
+    SignedRange { min: i16, max: i16 },
+    OneOf(&'static [u16]),
+}
+
+impl Validation {
+    pub fn is_valid(&self, value: u16) -> bool {
+        match self {
+            Validation::None => true,
+            Validation::Range { min, max } => (*min..=*max).contains(&value),
+            Validation::SignedRange { min, max } => (*min..=*max).contains(&(value as i16)),
+            Validation::OneOf(values) => values.contains(&value),
+        }
+    }
+}
+
+/// Integer stored in the registers of a scaled value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RawType {
+    U16,
+    I16,
+    U32,
+    I32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterType {
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    Bool,
+    /// Fixed point, value is `raw * scale`
+    Scaled { raw: RawType, scale: f32 },
+}
+
+impl RegisterType {
+    /// Number of consecutive registers used by the value
+    pub const fn words(&self) -> u16 {
+        match self {
+            RegisterType::U32 | RegisterType::I32 | RegisterType::F32 => 2,
+            RegisterType::Scaled { raw: RawType::U32 | RawType::I32, .. } => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Order of the registers of 32 bit values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WordOrder {
+    /// Most significant word at the lower address
+    HighFirst,
+    LowFirst,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Register {
+    pub name: &'static str,
+    pub area: Area,
+    pub address: u16,
+    pub ty: RegisterType,
+    pub word_order: WordOrder,
+    pub access: Access,
+    /// Checked for single register values only
+    pub validation: Validation,
+    pub unit: &'static str,
+}
+
+impl Register {
+    /// Coils and holdings are read-write, discrete inputs and input registers read-only
+    pub const fn new(name: &'static str, area: Area, address: u16, ty: RegisterType) -> Self {
+        let access = match area {
+            Area::Coil | Area::Holding => Access::ReadWrite,
+            Area::Discrete | Area::Input => Access::ReadOnly,
+        };
+        Self { name, area, address, ty, word_order: WordOrder::HighFirst, access, validation: Validation::None, unit: "" }
+    }
+
+    pub const fn read_only(mut self) -> Self {
+        self.access = Access::ReadOnly;
+        self
+    }
+
+    pub const fn word_order(mut self, word_order: WordOrder) -> Self {
+        self.word_order = word_order;
+        self
+    }
+
+    pub const fn validated(mut self, validation: Validation) -> Self {
+        self.validation = validation;
+        self
+    }
+
+    pub const fn unit(mut self, unit: &'static str) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    pub const fn words(&self) -> u16 {
+        self.ty.words()
+    }
+
+    pub fn contains(&self, area: Area, reg: u16) -> bool {
+        self.area == area && reg >= self.address && (reg - self.address) < self.words()
+    }
+}
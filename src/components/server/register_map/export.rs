@@ -0,0 +1,142 @@
+//! CSV and JSON export of register descriptions for HMI/SCADA tools.
+//!
+//! One row or object per `Register`, 32 bit values report two words and their word order.
+
+use core::fmt::{self, Display, Write};
+
+use super::def::{Access, Area, RawType, Register, RegisterType, WordOrder};
+
+const CSV_HEADER: &str = "name,area,address,words,type,scale,word_order,unit,access";
+
+fn area_str(area: Area) -> &'static str {
+    match area {
+        Area::Coil => "coil",
+        Area::Discrete => "discrete",
+        Area::Input => "input",
+        Area::Holding => "holding",
+    }
+}
+
+fn raw_type_str(raw: RawType) -> &'static str {
+    match raw {
+        RawType::U16 => "u16",
+        RawType::I16 => "i16",
+        RawType::U32 => "u32",
+        RawType::I32 => "i32",
+    }
+}
+
+/// Stored type, the scale of fixed point values is exported separately
+fn type_str(ty: RegisterType) -> &'static str {
+    match ty {
+        RegisterType::U16 => "u16",
+        RegisterType::I16 => "i16",
+        RegisterType::U32 => "u32",
+        RegisterType::I32 => "i32",
+        RegisterType::F32 => "f32",
+        RegisterType::Bool => "bool",
+        RegisterType::Scaled { raw, .. } => raw_type_str(raw),
+    }
+}
+
+fn scale(ty: RegisterType) -> f32 {
+    match ty {
+        RegisterType::Scaled { scale, .. } => scale,
+        _ => 1.0,
+    }
+}
+
+fn word_order_str(order: WordOrder) -> &'static str {
+    match order {
+        WordOrder::HighFirst => "high_first",
+        WordOrder::LowFirst => "low_first",
+    }
+}
+
+fn access_str(access: Access) -> &'static str {
+    match access {
+        Access::ReadOnly => "ro",
+        Access::ReadWrite => "rw",
+    }
+}
+
+/// CSV field, quoted if needed
+struct Csv<'a>(&'a str);
+
+impl Display for Csv<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.0.contains([',', '"', '\n', '\r']) {
+            return f.write_str(self.0);
+        }
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            if c == '"' {
+                f.write_char('"')?;
+            }
+            f.write_char(c)?;
+        }
+        f.write_char('"')
+    }
+}
+
+/// Quoted JSON string
+struct Json<'a>(&'a str);
+
+impl Display for Json<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                c => f.write_char(c)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+pub fn write_csv<W: Write>(w: &mut W, registers: &[Register]) -> fmt::Result {
+    writeln!(w, "{}", CSV_HEADER)?;
+    for r in registers {
+        writeln!(
+            w,
+            "{},{},{},{},{},{},{},{},{}",
+            Csv(r.name),
+            area_str(r.area),
+            r.address,
+            r.words(),
+            type_str(r.ty),
+            scale(r.ty),
+            word_order_str(r.word_order),
+            Csv(r.unit),
+            access_str(r.access),
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_json<W: Write>(w: &mut W, registers: &[Register]) -> fmt::Result {
+    writeln!(w, "[")?;
+    for (i, r) in registers.iter().enumerate() {
+        write!(
+            w,
+            "  {{\"name\": {}, \"area\": \"{}\", \"address\": {}, \"words\": {}, \"type\": \"{}\", \"scale\": {}, \"word_order\": \"{}\", \"unit\": {}, \"access\": \"{}\"}}",
+            Json(r.name),
+            area_str(r.area),
+            r.address,
+            r.words(),
+            type_str(r.ty),
+            scale(r.ty),
+            word_order_str(r.word_order),
+            Json(r.unit),
+            access_str(r.access),
+        )?;
+        writeln!(w, "{}", if i + 1 < registers.len() { "," } else { "" })?;
+    }
+    writeln!(w, "]")
+}
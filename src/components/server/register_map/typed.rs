@@ -0,0 +1,236 @@
+//! Named registers with a value type, declared as consts:
+//!
+//! ```ignore
+//! const SPEED: TypedRegister<f32> = TypedRegister::scaled("speed", Area::Holding, 10, RawType::I16, 0.1).unit("rpm");
+//! const COUNTER: TypedRegister<u32> = TypedRegister::new("counter", Area::Input, 20).word_order(WordOrder::LowFirst);
+//! const REGISTERS: &[Register] = &[SPEED.register, COUNTER.register];
+//! static MAP: RegisterMap = RegisterMap::new(&[]).with_registers(REGISTERS);
+//! ```
+
+use core::marker::PhantomData;
+
+use embassy_sync::{blocking_mutex::raw::RawMutex, mutex::Mutex};
+
+pub use super::def::{RawType, Register, RegisterType, WordOrder};
+use super::{Area, Exception, Validation};
+use crate::components::server::register_provider::RegisterProvider;
+
+/// Decoded register value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f32),
+    Bool(bool),
+}
+
+impl Register {
+    fn read_word<P: RegisterProvider + ?Sized>(&self, provider: &P, reg: u16) -> Result<u16, Exception> {
+        match self.area {
+            Area::Coil => provider.coil(reg).map(|v| v as u16),
+            Area::Discrete => provider.discrete(reg).map(|v| v as u16),
+            Area::Input => provider.input(reg),
+            Area::Holding => provider.holding(reg),
+        }
+    }
+
+    fn write_word<P: RegisterProvider + ?Sized>(&self, provider: &mut P, reg: u16, value: u16) -> Result<(), Exception> {
+        match self.area {
+            Area::Coil => provider.set_coil(reg, value != 0),
+            Area::Discrete => provider.set_discrete(reg, value != 0),
+            Area::Input => provider.set_input(reg, value),
+            Area::Holding => provider.set_holding(reg, value),
+        }
+    }
+
+    fn read_u32<P: RegisterProvider + ?Sized>(&self, provider: &P) -> Result<u32, Exception> {
+        let first = self.read_word(provider, self.address)? as u32;
+        if self.words() == 1 {
+            return Ok(first);
+        }
+        let second = self.read_word(provider, self.address.checked_add(1).ok_or(Exception::IllegalDataAddress)?)? as u32;
+        Ok(match self.word_order {
+            WordOrder::HighFirst => first << 16 | second,
+            WordOrder::LowFirst => second << 16 | first,
+        })
+    }
+
+    fn write_u32<P: RegisterProvider + ?Sized>(&self, provider: &mut P, value: u32) -> Result<(), Exception> {
+        if self.words() == 1 {
+            return self.write_word(provider, self.address, value as u16);
+        }
+        let (first, second) = match self.word_order {
+            WordOrder::HighFirst => ((value >> 16) as u16, value as u16),
+            WordOrder::LowFirst => (value as u16, (value >> 16) as u16),
+        };
+        self.write_word(provider, self.address, first)?;
+        self.write_word(provider, self.address.checked_add(1).ok_or(Exception::IllegalDataAddress)?, second)
+    }
+
+    pub fn read<P: RegisterProvider + ?Sized>(&self, provider: &P) -> Result<Value, Exception> {
+        let raw = self.read_u32(provider)?;
+        Ok(match self.ty {
+            RegisterType::Bool => Value::Bool(raw != 0),
+            RegisterType::F32 => Value::Float(f32::from_bits(raw)),
+            RegisterType::Scaled { raw: raw_type, scale } => Value::Float(decode_int(raw_type, raw) as f32 * scale),
+            RegisterType::U16 => Value::Int(decode_int(RawType::U16, raw)),
+            RegisterType::I16 => Value::Int(decode_int(RawType::I16, raw)),
+            RegisterType::U32 => Value::Int(decode_int(RawType::U32, raw)),
+            RegisterType::I32 => Value::Int(decode_int(RawType::I32, raw)),
+        })
+    }
+
+    /// Store `value`, fails with `IllegalDataValue` if it does not fit the register type
+    pub fn write<P: RegisterProvider + ?Sized>(&self, provider: &mut P, value: Value) -> Result<(), Exception> {
+        let raw = match (self.ty, value) {
+            (RegisterType::Bool, Value::Bool(v)) => v as u32,
+            (RegisterType::F32, Value::Float(v)) => v.to_bits(),
+            (RegisterType::Scaled { raw, scale }, Value::Float(v)) => encode_int(raw, round(v / scale))?,
+            (RegisterType::U16, Value::Int(v)) => encode_int(RawType::U16, v)?,
+            (RegisterType::I16, Value::Int(v)) => encode_int(RawType::I16, v)?,
+            (RegisterType::U32, Value::Int(v)) => encode_int(RawType::U32, v)?,
+            (RegisterType::I32, Value::Int(v)) => encode_int(RawType::I32, v)?,
+            _ => return Err(Exception::IllegalDataValue),
+        };
+        self.write_u32(provider, raw)
+    }
+}
+
+/// Round half away from zero, `as` saturates out of range values
+fn round(value: f32) -> i64 {
+    if value >= 0.0 { (value + 0.5) as i64 } else { (value - 0.5) as i64 }
+}
+
+fn decode_int(ty: RawType, raw: u32) -> i64 {
+    match ty {
+        RawType::U16 => raw as u16 as i64,
+        RawType::I16 => raw as u16 as i16 as i64,
+        RawType::U32 => raw as i64,
+        RawType::I32 => raw as i32 as i64,
+    }
+}
+
+fn encode_int(ty: RawType, value: i64) -> Result<u32, Exception> {
+    let res = match ty {
+        RawType::U16 => u16::try_from(value).map(|v| v as u32).ok(),
+        RawType::I16 => i16::try_from(value).map(|v| v as u16 as u32).ok(),
+        RawType::U32 => u32::try_from(value).ok(),
+        RawType::I32 => i32::try_from(value).map(|v| v as u32).ok(),
+    };
+    res.ok_or(Exception::IllegalDataValue)
+}
+
+/// Rust type of a register value
+pub trait RegisterValue: Sized {
+    const TYPE: RegisterType;
+
+    fn from_value(value: Value) -> Option<Self>;
+
+    fn into_value(self) -> Value;
+}
+
+macro_rules! impl_int_value {
+    ($($t:ty => $ty:ident),*) => {$(
+        impl RegisterValue for $t {
+            const TYPE: RegisterType = RegisterType::$ty;
+
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::Int(v) => <$t>::try_from(v).ok(),
+                    _ => None,
+                }
+            }
+
+            fn into_value(self) -> Value {
+                Value::Int(self as i64)
+            }
+        }
+    )*};
+}
+
+impl_int_value!(u16 => U16, i16 => I16, u32 => U32, i32 => I32);
+
+impl RegisterValue for f32 {
+    const TYPE: RegisterType = RegisterType::F32;
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Float(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+impl RegisterValue for bool {
+    const TYPE: RegisterType = RegisterType::Bool;
+
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(v) => Some(v),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+/// `Register` with typed accessors. Accessors ignore `access`, it only restricts the bus side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TypedRegister<T> {
+    pub register: Register,
+    _value: PhantomData<T>,
+}
+
+impl<T: RegisterValue> TypedRegister<T> {
+    pub const fn new(name: &'static str, area: Area, address: u16) -> Self {
+        Self { register: Register::new(name, area, address, T::TYPE), _value: PhantomData }
+    }
+
+    pub const fn read_only(mut self) -> Self {
+        self.register = self.register.read_only();
+        self
+    }
+
+    pub const fn word_order(mut self, word_order: WordOrder) -> Self {
+        self.register = self.register.word_order(word_order);
+        self
+    }
+
+    pub const fn validated(mut self, validation: Validation) -> Self {
+        self.register = self.register.validated(validation);
+        self
+    }
+
+    pub const fn unit(mut self, unit: &'static str) -> Self {
+        self.register = self.register.unit(unit);
+        self
+    }
+
+    pub fn get<P: RegisterProvider + ?Sized>(&self, provider: &P) -> Result<T, Exception> {
+        T::from_value(self.register.read(provider)?).ok_or(Exception::IllegalDataValue)
+    }
+
+    pub fn set<P: RegisterProvider + ?Sized>(&self, provider: &mut P, value: T) -> Result<(), Exception> {
+        self.register.write(provider, value.into_value())
+    }
+
+    pub async fn load<M: RawMutex, P: RegisterProvider>(&self, storage: &Mutex<M, P>) -> Result<T, Exception> {
+        self.get(&*storage.lock().await)
+    }
+
+    pub async fn store<M: RawMutex, P: RegisterProvider>(&self, storage: &Mutex<M, P>, value: T) -> Result<(), Exception> {
+        self.set(&mut *storage.lock().await, value)
+    }
+}
+
+impl TypedRegister<f32> {
+    /// Fixed point value stored as `raw` integer, `value = raw * scale`
+    pub const fn scaled(name: &'static str, area: Area, address: u16, raw: RawType, scale: f32) -> Self {
+        Self { register: Register::new(name, area, address, RegisterType::Scaled { raw, scale }), _value: PhantomData }
+    }
+}
@@ -0,0 +1,78 @@
+use rmodbus::server::{context::ModbusContext, storage::ModbusStorage};
+
+use super::register_map::{Area, Exception};
+
+/// Data model served by `ModbusServer` and `ModbusTcpServer`. Implement it to compute registers
+/// on the fly, errors are answered as Modbus exceptions.
+pub trait RegisterProvider {
+    fn coil(&self, reg: u16) -> Result<bool, Exception>;
+
+    fn discrete(&self, reg: u16) -> Result<bool, Exception>;
+
+    fn input(&self, reg: u16) -> Result<u16, Exception>;
+
+    fn holding(&self, reg: u16) -> Result<u16, Exception>;
+
+    fn set_coil(&mut self, reg: u16, value: bool) -> Result<(), Exception>;
+
+    fn set_holding(&mut self, reg: u16, value: u16) -> Result<(), Exception>;
+
+    /// Check that every coil or holding register of `start..start + count` accepts a write, so a
+    /// multiple write is applied completely or not at all. The default probes each address with
+    /// the read accessors, override it if writable addresses differ from readable ones.
+    fn check_writable(&self, area: Area, start: u16, count: u16) -> Result<(), Exception> {
+        for i in 0..count {
+            let reg = start.checked_add(i).ok_or(Exception::IllegalDataAddress)?;
+            match area {
+                Area::Coil => self.coil(reg).map(|_| ())?,
+                Area::Holding => self.holding(reg).map(|_| ())?,
+                Area::Discrete | Area::Input => return Err(Exception::IllegalDataAddress),
+            }
+        }
+        Ok(())
+    }
+
+    /// Only used by the firmware side, e.g. to mirror server statistics
+    fn set_input(&mut self, _reg: u16, _value: u16) -> Result<(), Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+
+    /// Only used by the firmware side
+    fn set_discrete(&mut self, _reg: u16, _value: bool) -> Result<(), Exception> {
+        Err(Exception::IllegalDataAddress)
+    }
+}
+
+impl<const C: usize, const D: usize, const I: usize, const H: usize> RegisterProvider for ModbusStorage<C,D,I,H> {
+    fn coil(&self, reg: u16) -> Result<bool, Exception> {
+        self.get_coil(reg).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn discrete(&self, reg: u16) -> Result<bool, Exception> {
+        self.get_discrete(reg).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn input(&self, reg: u16) -> Result<u16, Exception> {
+        self.get_input(reg).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn holding(&self, reg: u16) -> Result<u16, Exception> {
+        self.get_holding(reg).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn set_coil(&mut self, reg: u16, value: bool) -> Result<(), Exception> {
+        ModbusContext::set_coil(self, reg, value).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn set_holding(&mut self, reg: u16, value: u16) -> Result<(), Exception> {
+        ModbusContext::set_holding(self, reg, value).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn set_input(&mut self, reg: u16, value: u16) -> Result<(), Exception> {
+        ModbusContext::set_input(self, reg, value).map_err(|_| Exception::IllegalDataAddress)
+    }
+
+    fn set_discrete(&mut self, reg: u16, value: bool) -> Result<(), Exception> {
+        ModbusContext::set_discrete(self, reg, value).map_err(|_| Exception::IllegalDataAddress)
+    }
+}